@@ -0,0 +1,50 @@
+//! Inputs shared by the benches, drawn like the real files
+
+use std::ops::Range;
+
+use one_billion_row_challenge_rust::{
+    cli::{GenerateArgs, NameKind},
+    generate::write_rows,
+};
+
+/// The official stations and their temperatures, and 10k random names, the
+/// most the rules allow
+pub fn datasets() -> [(&'static str, Vec<u8>); 2] {
+    [
+        ("official", measurements(NameKind::Official, 413)),
+        ("10k", measurements(NameKind::Random, 10_000)),
+    ]
+}
+
+/// 200k rows, a few MB: the stations all show up, and the input stays in
+/// the cache
+fn measurements(names: NameKind, stations: usize) -> Vec<u8> {
+    let args = GenerateArgs {
+        rows: 200_000,
+        out: "unused".into(),
+        seed: 1,
+        stations,
+        names,
+        extreme: false,
+        threads: None,
+    };
+    let mut out = vec![];
+    write_rows(&args, 1, &mut out).unwrap();
+    out
+}
+
+/// The name and the temperature of every line
+// each bench compiles its own copy of the module, `run` doesn't need it
+#[allow(dead_code)]
+pub fn lines(data: &[u8]) -> Vec<(Range<usize>, Range<usize>)> {
+    let mut start = 0;
+    data.split_inclusive(|&b| b == b'\n')
+        .map(|line| {
+            let sc = start + line.iter().position(|&b| b == b';').unwrap();
+            let nl = start + line.len() - 1;
+            let ranges = (start..sc, sc + 1..nl);
+            start += line.len();
+            ranges
+        })
+        .collect()
+}
@@ -0,0 +1,87 @@
+use std::hash::BuildHasher;
+
+use criterion::{
+    BenchmarkGroup, Criterion, Throughput, criterion_group, criterion_main, measurement::WallTime,
+};
+use one_billion_row_challenge_rust::hasher::{
+    FasHaserBuilder, FasHaserBuilderSimd, ScanHasher, SeededHasherBuilder, StationHasherBuilder,
+};
+use rustc_hash::FxBuildHasher;
+
+mod common;
+
+/// The names of the lines, in the order of the file, and the first 16 bytes
+/// of each line as the scanners load them
+fn names(data: &[u8]) -> (Vec<&[u8]>, Vec<u128>) {
+    common::lines(data)
+        .into_iter()
+        .map(|(name, _)| {
+            let mut head = [0; 16];
+            let line = &data[name.start..];
+            let n = line.len().min(16);
+            head[..n].copy_from_slice(&line[..n]);
+            (&data[name], u128::from_le_bytes(head))
+        })
+        .unzip()
+}
+
+/// What the maps hash for every line when they aren't given the hash
+fn hash_one(
+    group: &mut BenchmarkGroup<WallTime>,
+    name: &str,
+    names: &[&[u8]],
+    hasher: impl BuildHasher,
+) {
+    group.bench_function(name, |b| {
+        b.iter(|| {
+            names
+                .iter()
+                .fold(0u64, |acc, name| acc.wrapping_add(hasher.hash_one(name)))
+        })
+    });
+}
+
+/// The hash the parser computes from the bytes it loaded
+fn hash_scanned(
+    group: &mut BenchmarkGroup<WallTime>,
+    name: &str,
+    (names, heads): &(Vec<&[u8]>, Vec<u128>),
+    hasher: impl ScanHasher,
+) {
+    group.bench_function(name, |b| {
+        b.iter(|| {
+            names.iter().zip(heads).fold(0u64, |acc, (name, &head)| {
+                acc.wrapping_add(hasher.hash_scanned(name, head))
+            })
+        })
+    });
+}
+
+fn hashers(c: &mut Criterion) {
+    for (dataset, data) in common::datasets() {
+        let lines = names(&data);
+        let mut group = c.benchmark_group(format!("hash_one/{dataset}"));
+        group.throughput(Throughput::Elements(lines.0.len() as u64));
+        hash_one(&mut group, "fx", &lines.0, FxBuildHasher);
+        hash_one(&mut group, "station", &lines.0, StationHasherBuilder);
+        hash_one(
+            &mut group,
+            "seeded",
+            &lines.0,
+            SeededHasherBuilder::default(),
+        );
+        hash_one(&mut group, "fas", &lines.0, FasHaserBuilder);
+        hash_one(&mut group, "fas_simd", &lines.0, FasHaserBuilderSimd);
+        group.finish();
+
+        let mut group = c.benchmark_group(format!("hash_scanned/{dataset}"));
+        group.throughput(Throughput::Elements(lines.0.len() as u64));
+        hash_scanned(&mut group, "fx", &lines, FxBuildHasher);
+        hash_scanned(&mut group, "station", &lines, StationHasherBuilder);
+        hash_scanned(&mut group, "seeded", &lines, SeededHasherBuilder::default());
+        group.finish();
+    }
+}
+
+criterion_group!(benches, hashers);
+criterion_main!(benches);
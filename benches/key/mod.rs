@@ -0,0 +1,165 @@
+//! Station names stored inline when they are short enough. The workers key
+//! their maps by slices of the input, only the `keys` bench uses these.
+
+use std::{
+    borrow::Borrow,
+    cmp::Ordering,
+    fmt::Debug,
+    hash::{BuildHasher, Hash, Hasher},
+    ops::Deref,
+};
+
+use rustc_hash::FxBuildHasher;
+
+/// Number of key bytes kept in the header
+static PREFIX_LEN: usize = 7;
+
+/// A byte string stored in place when it is at most `N` bytes long, and
+/// boxed otherwise.
+///
+/// The first bytes and the length are packed in a `header` word, comparing
+/// it settles most comparisons without looking at the bytes themselves.
+/// `Hash`, `Eq` and `Ord` all agree with `[u8]`'s, so maps of `InlineKey`
+/// can be queried with plain `&[u8]`.
+#[derive(Clone)]
+pub struct InlineKey<const N: usize> {
+    /// big endian prefix on the 7 high bytes, saturated length on the low one
+    header: u64,
+    repr: Repr<N>,
+}
+
+#[derive(Clone)]
+enum Repr<const N: usize> {
+    Inline([u8; N]),
+    Heap(Box<[u8]>),
+}
+
+impl<const N: usize> InlineKey<N> {
+    /// the length of inline keys is read back from the header
+    const FITS_HEADER: () = assert!(N <= u8::MAX as usize);
+
+    fn header(bytes: &[u8]) -> u64 {
+        let mut header = [0; 8];
+        let n = bytes.len().min(PREFIX_LEN);
+        header[..n].copy_from_slice(&bytes[..n]);
+        header[PREFIX_LEN] = bytes.len().min(u8::MAX as usize) as u8;
+        u64::from_be_bytes(header)
+    }
+
+    pub fn as_slice(&self) -> &[u8] {
+        match &self.repr {
+            Repr::Inline(bytes) => {
+                let len = (self.header & 0xFF) as usize;
+                // safety: inline keys are at most `N` long
+                unsafe { bytes.get_unchecked(..len) }
+            }
+            Repr::Heap(bytes) => bytes,
+        }
+    }
+
+    pub fn is_inline(&self) -> bool {
+        matches!(self.repr, Repr::Inline(_))
+    }
+}
+
+impl<const N: usize> From<&[u8]> for InlineKey<N> {
+    fn from(value: &[u8]) -> Self {
+        #[allow(clippy::let_unit_value)]
+        let () = Self::FITS_HEADER;
+
+        let repr = if value.len() <= N {
+            let mut bytes = [0; N];
+            bytes[..value.len()].copy_from_slice(value);
+            Repr::Inline(bytes)
+        } else {
+            Repr::Heap(value.into())
+        };
+        Self {
+            header: Self::header(value),
+            repr,
+        }
+    }
+}
+
+impl<const N: usize> Deref for InlineKey<N> {
+    type Target = [u8];
+
+    fn deref(&self) -> &Self::Target {
+        self.as_slice()
+    }
+}
+
+impl<const N: usize> Borrow<[u8]> for InlineKey<N> {
+    fn borrow(&self) -> &[u8] {
+        self.as_slice()
+    }
+}
+
+impl<const N: usize> PartialEq for InlineKey<N> {
+    fn eq(&self, other: &Self) -> bool {
+        self.header == other.header && self.as_slice() == other.as_slice()
+    }
+}
+
+impl<const N: usize> Eq for InlineKey<N> {}
+
+impl<const N: usize> PartialOrd for InlineKey<N> {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl<const N: usize> Ord for InlineKey<N> {
+    fn cmp(&self, other: &Self) -> Ordering {
+        // the zero padded big endian prefixes order like the bytes they hold
+        match (self.header >> 8).cmp(&(other.header >> 8)) {
+            Ordering::Equal => self.as_slice().cmp(other.as_slice()),
+            ord => ord,
+        }
+    }
+}
+
+impl<const N: usize> Hash for InlineKey<N> {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        self.as_slice().hash(state)
+    }
+}
+
+impl<const N: usize> Debug for InlineKey<N> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        Debug::fmt(&self.as_slice().escape_ascii(), f)
+    }
+}
+
+/// Panics unless `InlineKey<N>` compares, orders and hashes `names` like
+/// the slices they hold, as the maps need. Zero bytes and lengths around `N`
+/// and the header prefix are added, they are where the header could lie.
+pub fn check<const N: usize>(names: &[&[u8]]) {
+    let edges: Vec<Vec<u8>> = [0, 1, 6, 7, 8, N - 1, N, N + 1]
+        .into_iter()
+        .flat_map(|len| {
+            [
+                vec![0; len],
+                vec![b'a'; len],
+                [vec![b'a'; len], vec![0]].concat(),
+            ]
+        })
+        .collect();
+    let mut all: Vec<&[u8]> = names.to_vec();
+    all.extend(edges.iter().map(|e| e.as_slice()));
+    all.sort_unstable();
+
+    let hasher = FxBuildHasher;
+    let mut keys: Vec<InlineKey<N>> = all.iter().map(|&name| name.into()).collect();
+    for (key, &name) in keys.iter().zip(&all) {
+        assert_eq!(key.as_slice(), name);
+        assert_eq!(key.is_inline(), name.len() <= N);
+        assert_eq!(hasher.hash_one(key), hasher.hash_one(name));
+    }
+    keys.reverse();
+    keys.sort_unstable();
+    assert!(keys.iter().map(|k| k.as_slice()).eq(all.iter().copied()));
+    for pair in keys.windows(2) {
+        assert_eq!(pair[0] == pair[1], pair[0].as_slice() == pair[1].as_slice());
+    }
+}
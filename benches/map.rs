@@ -0,0 +1,135 @@
+use std::{borrow::Borrow, collections::HashSet, hash::Hash};
+
+use criterion::{
+    BatchSize, BenchmarkGroup, Criterion, Throughput, criterion_group, criterion_main,
+    measurement::WallTime,
+};
+use one_billion_row_challenge_rust::{hasher::MHasher, hashmap::StackMap};
+
+use crate::key::InlineKey;
+
+mod common;
+mod key;
+
+type HashbrownMap<'a> = hashbrown::HashMap<&'a [u8], u64, MHasher>;
+
+/// Lookups in the order of the lines of the file, and inserts of every
+/// station, with the hasher of the runs
+fn maps(c: &mut Criterion) {
+    for (dataset, data) in common::datasets() {
+        let names: Vec<_> = common::lines(&data)
+            .into_iter()
+            .map(|(name, _)| &data[name])
+            .collect();
+        let mut stations: Vec<_> = names
+            .iter()
+            .copied()
+            .collect::<HashSet<_>>()
+            .into_iter()
+            .collect();
+        stations.sort_unstable();
+
+        let mut stack = StackMap::<&[u8], u64, MHasher>::new_boxed();
+        let mut hashbrown = HashbrownMap::with_capacity_and_hasher(10_000, MHasher::default());
+        for &station in &stations {
+            stack.insert(station, 1);
+            hashbrown.insert(station, 1);
+        }
+
+        let mut group = c.benchmark_group(format!("get/{dataset}"));
+        group.throughput(Throughput::Elements(names.len() as u64));
+        group.bench_function("stack", |b| {
+            b.iter(|| {
+                names
+                    .iter()
+                    .map(|name| stack.get(name).unwrap())
+                    .sum::<u64>()
+            })
+        });
+        group.bench_function("hashbrown", |b| {
+            b.iter(|| names.iter().map(|name| hashbrown[name]).sum::<u64>())
+        });
+        group.finish();
+
+        let mut group = c.benchmark_group(format!("insert/{dataset}"));
+        group.throughput(Throughput::Elements(stations.len() as u64));
+        group.bench_function("stack", |b| {
+            b.iter_batched(
+                StackMap::<&[u8], u64, MHasher>::new_boxed,
+                |mut map| {
+                    for &station in &stations {
+                        map.insert(station, 1);
+                    }
+                    map
+                },
+                BatchSize::LargeInput,
+            )
+        });
+        group.bench_function("hashbrown", |b| {
+            b.iter_batched(
+                || HashbrownMap::with_capacity_and_hasher(10_000, MHasher::default()),
+                |mut map| {
+                    for &station in &stations {
+                        map.insert(station, 1);
+                    }
+                    map
+                },
+                BatchSize::LargeInput,
+            )
+        });
+        group.finish();
+    }
+}
+
+/// Lookups in the order of the lines of the file, in a map of owned `K`
+fn lookup_owned<K>(
+    group: &mut BenchmarkGroup<WallTime>,
+    name: &str,
+    stations: &[&[u8]],
+    names: &[&[u8]],
+) where
+    K: for<'a> From<&'a [u8]> + Borrow<[u8]> + Hash + Ord,
+{
+    let mut map = StackMap::<K, u64, MHasher>::new_boxed();
+    for &station in stations {
+        map.insert(station.into(), 1);
+    }
+    group.bench_function(name, |b| {
+        b.iter(|| {
+            names
+                .iter()
+                .map(|&name| map.get(name).unwrap())
+                .sum::<u64>()
+        })
+    });
+}
+
+/// The owned keys, for when the input doesn't outlive the maps: boxed, or
+/// inline up to 15 and 23 bytes
+fn keys(c: &mut Criterion) {
+    for (dataset, data) in common::datasets() {
+        let names: Vec<_> = common::lines(&data)
+            .into_iter()
+            .map(|(name, _)| &data[name])
+            .collect();
+        let stations: Vec<_> = names
+            .iter()
+            .copied()
+            .collect::<HashSet<_>>()
+            .into_iter()
+            .collect();
+
+        key::check::<15>(&stations);
+        key::check::<23>(&stations);
+
+        let mut group = c.benchmark_group(format!("keys/{dataset}"));
+        group.throughput(Throughput::Elements(names.len() as u64));
+        lookup_owned::<Box<[u8]>>(&mut group, "boxed", &stations, &names);
+        lookup_owned::<InlineKey<15>>(&mut group, "inline_15", &stations, &names);
+        lookup_owned::<InlineKey<23>>(&mut group, "inline_23", &stations, &names);
+        group.finish();
+    }
+}
+
+criterion_group!(benches, maps, keys);
+criterion_main!(benches);
@@ -0,0 +1,145 @@
+use std::{hint::black_box, ops::Range};
+
+use criterion::{
+    BenchmarkGroup, Criterion, Throughput, criterion_group, criterion_main, measurement::WallTime,
+};
+#[cfg(target_arch = "aarch64")]
+use one_billion_row_challenge_rust::parser::Neon;
+#[cfg(target_arch = "x86_64")]
+use one_billion_row_challenge_rust::parser::{Avx2, Avx512, Avx512Blocks, Sse2, X86V3};
+use one_billion_row_challenge_rust::{
+    fsize,
+    parser::{Scanner, Swar, find_next, old_parse, parse_value, semi_smart, ssize},
+};
+
+mod common;
+
+/// Finds every line of `data`, if the cpu has the scanner
+fn scan<S: Scanner>(group: &mut BenchmarkGroup<WallTime>, name: &str, data: &[u8]) {
+    let Some(scanner) = S::detect() else {
+        return;
+    };
+    group.bench_function(name, |b| {
+        b.iter(|| {
+            scanner.run(|| {
+                let (mut scanner, mut current, mut lines) = (scanner, 0, 0);
+                while let Some((_, nl, _)) = find_next(&mut scanner, black_box(data), current) {
+                    current += nl + 1;
+                    lines += 1;
+                }
+                lines
+            })
+        })
+    });
+}
+
+fn find_lines(c: &mut Criterion) {
+    for (dataset, data) in common::datasets() {
+        let mut group = c.benchmark_group(format!("find_next/{dataset}"));
+        group.throughput(Throughput::Bytes(data.len() as u64));
+        scan::<Swar>(&mut group, "swar", &data);
+        #[cfg(target_arch = "x86_64")]
+        {
+            scan::<Sse2>(&mut group, "sse2", &data);
+            scan::<X86V3>(&mut group, "x86-64-v3", &data);
+            scan::<Avx2>(&mut group, "avx2", &data);
+            scan::<Avx512>(&mut group, "avx512", &data);
+            scan::<Avx512Blocks>(&mut group, "avx512-blocks", &data);
+        }
+        #[cfg(target_arch = "aarch64")]
+        scan::<Neon>(&mut group, "neon", &data);
+        group.finish();
+    }
+}
+
+/// Sums the `temperatures` of `data`, `parse` is a fn item so that it inlines
+fn parse_all(
+    group: &mut BenchmarkGroup<WallTime>,
+    name: &str,
+    data: &[u8],
+    temperatures: &[Range<usize>],
+    parse: impl Fn(&[u8], usize, usize) -> fsize,
+) {
+    group.bench_function(name, |b| {
+        b.iter(|| {
+            let data = black_box(data);
+            temperatures
+                .iter()
+                .map(|t| i64::from(parse(data, t.start, t.end)))
+                .sum::<i64>()
+        })
+    });
+}
+
+fn parse_temperatures(c: &mut Criterion) {
+    let [(_, data), _] = common::datasets();
+    let temperatures: Vec<_> = common::lines(&data).into_iter().map(|(_, t)| t).collect();
+
+    let mut group = c.benchmark_group("parse_value");
+    group.throughput(Throughput::Elements(temperatures.len() as u64));
+    parse_all(&mut group, "parse_value", &data, &temperatures, parse_value);
+    parse_all(&mut group, "old_parse", &data, &temperatures, old_parse);
+    parse_all(&mut group, "semi_smart", &data, &temperatures, semi_smart);
+    parse_all(
+        &mut group,
+        "parse_value_unchecked",
+        &data,
+        &temperatures,
+        parse_value_unchecked,
+    );
+    group.finish();
+}
+
+/// [`parse_value`] with the unchecked loads it had before they were bounds
+/// checked
+fn parse_value_unchecked(str: &[u8], start: usize, end: usize) -> fsize {
+    // safety: the temperatures of the file are in bounds
+    let chunk = u32::from_le(unsafe { (str.as_ptr().add(end - 4) as *const u32).read_unaligned() });
+    let sign = unsafe { *str.get_unchecked(start) } == b'-';
+
+    let dec = ((chunk >> 24) as u8 & 0x0F) as i16;
+    let unit = ((chunk >> 8) as u8 & 0x0F) as i16;
+    let ten = (chunk as u8).wrapping_sub(b'0');
+    let has_4th = ten < 10;
+
+    let res = dec + 10 * unit + 100 * (has_4th as i16) * (ten as i16);
+    let mask = -(sign as fsize);
+    (res ^ mask) - mask
+}
+
+/// The 16 bytes word load of the scanners at every line, as a bounds checked
+/// slice and as a raw read: what the bounds checks cost in the scan
+fn loads(c: &mut Criterion) {
+    for (dataset, data) in common::datasets() {
+        let starts: Vec<_> = common::lines(&data)
+            .into_iter()
+            .map(|(name, _)| name.start)
+            .filter(|&start| start + 16 <= data.len())
+            .collect();
+        let mut group = c.benchmark_group(format!("loads/{dataset}"));
+        group.throughput(Throughput::Elements(starts.len() as u64));
+        group.bench_function("checked", |b| {
+            b.iter(|| {
+                let data = black_box(&data[..]);
+                starts.iter().fold(0, |acc, &start| {
+                    acc ^ ssize::from_le_bytes(data[start..start + 16].try_into().unwrap())
+                })
+            })
+        });
+        group.bench_function("unchecked", |b| {
+            b.iter(|| {
+                let data = black_box(&data[..]);
+                starts.iter().fold(0, |acc, &start| {
+                    // safety: `start + 16 <= data.len()`
+                    acc ^ ssize::from_le(unsafe {
+                        (data.as_ptr().add(start) as *const ssize).read_unaligned()
+                    })
+                })
+            })
+        });
+        group.finish();
+    }
+}
+
+criterion_group!(benches, find_lines, parse_temperatures, loads);
+criterion_main!(benches);
@@ -0,0 +1,46 @@
+use std::hint::black_box;
+
+use criterion::{Criterion, Throughput, criterion_group, criterion_main};
+use one_billion_row_challenge_rust::{
+    aggregate_bytes,
+    cli::{Args, HasherKind, ScannerKind},
+    stats::Stat,
+};
+
+mod common;
+
+/// The whole run on one thread, with each hasher of the maps
+fn hashers(c: &mut Criterion) {
+    for (dataset, data) in common::datasets() {
+        let mut group = c.benchmark_group(format!("run/{dataset}"));
+        group.throughput(Throughput::Bytes(data.len() as u64));
+        for (name, hasher) in [
+            ("fx", HasherKind::Fx),
+            ("station", HasherKind::Station),
+            ("seeded", HasherKind::Seeded),
+        ] {
+            let args = Args {
+                hasher: Some(hasher),
+                ..Default::default()
+            };
+            let mut out = vec![];
+            group.bench_function(name, |b| {
+                b.iter(|| {
+                    out.clear();
+                    aggregate_bytes::<Stat>(
+                        black_box(&data),
+                        &args,
+                        ScannerKind::best(),
+                        1,
+                        &mut out,
+                    )
+                    .unwrap();
+                })
+            });
+        }
+        group.finish();
+    }
+}
+
+criterion_group!(benches, hashers);
+criterion_main!(benches);
@@ -43,6 +43,15 @@
         treefmtEval = treefmt-nix.lib.evalModule pkgs ./nix/fmt.nix;
 
         e-packages = (import ./nix/packages.nix) { inherit pkgs onebrc; };
+
+        # The parser tests under Miri, which fails on any read out of bounds:
+        # `nix run .#miri`. Proptest saves its failures to files, so Miri
+        # needs the file system, and fewer cases keep it in minutes.
+        miriScript = pkgs.writeShellScriptBin "miri-parser" ''
+          export PATH=${rust}/bin:$PATH
+          export MIRIFLAGS="-Zmiri-disable-isolation" PROPTEST_CASES=16
+          exec cargo miri test --lib parser:: "$@"
+        '';
       in
       rec {
         # Expose the package
@@ -66,7 +75,10 @@
             default = generateScript;
             fastest_java = fastestJava;
           }
-        );
+        )
+        // {
+          miri = flake-utils.lib.mkApp { drv = miriScript; };
+        };
 
         formatter = treefmtEval.config.build.wrapper;
 
@@ -76,6 +88,7 @@
             packages.generateScript
             packages.fastestJava
             packages.solutionScript
+            miriScript
             pkgs.graalvmPackages.graalvm-ce
             pkgs.nixd
             rust
@@ -0,0 +1,82 @@
+//! What the workers keep per station, see [`Aggregator`]. The run uses
+//! [`Stat`](crate::stats::Stat), [`challenge_with`](crate::challenge_with)
+//! runs any other.
+
+use std::fmt::Display;
+
+use crate::{fsize, merge::Merge};
+
+/// The aggregate of a station's temperatures. Each worker folds the
+/// temperatures of its part in with `update`, starting from the default, the
+/// values of the workers are then combined with [`Merge::merge`], in no
+/// particular order, and `finish` gives what is printed after the name.
+pub trait Aggregator: Merge + Default + Clone + Send + Sync {
+    type Output: Display;
+
+    /// Called for every row in the hot loop, it should inline
+    fn update(&mut self, temperature: fsize);
+
+    fn finish(&self) -> Self::Output;
+}
+
+#[cfg(test)]
+mod test {
+    use rustc_hash::FxBuildHasher;
+
+    use super::Aggregator;
+    use crate::{
+        BorrowedKey, aggregate,
+        cli::{Args, ScannerKind},
+        fsize,
+        hashmap::StackMap,
+        merge::Merge,
+    };
+
+    /// The rows and the warmest temperature, without the rest of `Stat`
+    #[derive(Clone, Copy, Default)]
+    struct CountMax {
+        count: u64,
+        max: Option<fsize>,
+    }
+
+    impl Merge for CountMax {
+        fn merge(&mut self, other: &Self) {
+            self.count += other.count;
+            self.max = self.max.max(other.max);
+        }
+    }
+
+    impl Aggregator for CountMax {
+        type Output = String;
+
+        fn update(&mut self, temperature: fsize) {
+            self.count += 1;
+            self.max = self.max.max(Some(temperature));
+        }
+
+        fn finish(&self) -> String {
+            format!("{}<{}", self.count, self.max.unwrap())
+        }
+    }
+
+    #[test]
+    fn runs_other_aggregators() {
+        let data = "b;1.0\na;-3.5\nb;-0.1\nc;99.9\na;-3.4\nb;0.0\n".repeat(100);
+        let data: Box<[u8]> = data.as_bytes().into();
+        for n_threads in [1, 3, 8] {
+            let mut out = vec![];
+            aggregate::<StackMap<BorrowedKey, CountMax, FxBuildHasher>, _>(
+                &data,
+                &Args::default(),
+                ScannerKind::Swar,
+                n_threads,
+                &mut out,
+            )
+            .unwrap();
+            assert_eq!(
+                String::from_utf8(out).unwrap(),
+                "{a=200<-34, b=300<10, c=100<999}\n"
+            );
+        }
+    }
+}
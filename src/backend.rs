@@ -0,0 +1,435 @@
+//! The maps the workers can aggregate into, to compare them without editing
+//! the source (see `--backend`)
+
+use std::{
+    borrow::Borrow,
+    collections::HashMap,
+    fmt::Display,
+    hash::{BuildHasher, Hash},
+};
+
+use hashbrown::{HashTable, hash_table::Entry};
+use intmap::IntMap;
+use smallvec::SmallVec;
+
+use crate::{
+    hash_quality::{CapacityStats, HashStat},
+    hashmap::StackMap,
+    merge::{self, Merge},
+};
+
+/// The heap backends are sized for the 10k stations the rules allow, so they
+/// never grow during the run
+static CAPACITY: usize = 10_000;
+
+/// A map from station names to their aggregates
+pub trait AggMap<K, V>: Sized
+where
+    K: Borrow<[u8]> + Hash + Ord,
+    V: Default,
+{
+    type Hasher: BuildHasher;
+
+    fn new_with_hasher(hasher: Self::Hasher) -> Self;
+
+    /// Same, on the heap, for the workers of unoptimized builds (see
+    /// [`WorkerMap`](crate::WorkerMap))
+    #[cfg(debug_assertions)]
+    fn new_boxed_with_hasher(hasher: Self::Hasher) -> Box<Self> {
+        Box::new(Self::new_with_hasher(hasher))
+    }
+
+    fn hasher(&self) -> &Self::Hasher;
+
+    /// The value of `key`, inserted with its default if new. `hashed` is the
+    /// hash of `key` under [`Self::hasher`], maps that can't take it hash
+    /// `key` themselves.
+    fn get_or_default<'b>(&mut self, hashed: u64, key: &'b [u8]) -> &mut V
+    where
+        K: From<&'b [u8]>;
+
+    fn iter<'m>(&'m self) -> impl Iterator<Item = (&'m K, &'m V)>
+    where
+        K: 'm,
+        V: 'm;
+
+    /// See [`StackMap::is_clustered`], only the linear probing map clusters
+    fn is_clustered(&self) -> bool {
+        false
+    }
+
+    /// Only called on clustered maps
+    fn rehash(&mut self, _hasher: Self::Hasher) {
+        unreachable!("only clustered maps are rehashed")
+    }
+
+    /// The entries of all the `maps`, merged and sorted by key
+    fn merge_all<'m, B>(maps: &'m [B], _n_threads: usize) -> Vec<(&'m K, V)>
+    where
+        B: Borrow<Self> + Sync,
+        Self: 'm,
+        K: Sync + 'm,
+        V: Merge + Clone + Send + Sync + 'm,
+    {
+        merge::merge_unordered(maps.iter().flat_map(|m| m.borrow().iter()))
+    }
+}
+
+impl<K, V, H> AggMap<K, V> for StackMap<K, V, H>
+where
+    K: Borrow<[u8]> + Hash + Ord,
+    V: Default,
+    H: BuildHasher + Sync,
+{
+    type Hasher = H;
+
+    fn new_with_hasher(hasher: H) -> Self {
+        StackMap::new_with_hasher(hasher)
+    }
+
+    /// In place, the map never goes through the stack
+    #[cfg(debug_assertions)]
+    fn new_boxed_with_hasher(hasher: H) -> Box<Self> {
+        StackMap::new_boxed_with_hasher(hasher)
+    }
+
+    fn hasher(&self) -> &H {
+        StackMap::hasher(self)
+    }
+
+    // the hot loop's lookup, it must inline into the scanner's `run`
+    #[inline(always)]
+    fn get_or_default<'b>(&mut self, hashed: u64, key: &'b [u8]) -> &mut V
+    where
+        K: From<&'b [u8]>,
+    {
+        // single lookup, the key is only built when it is new
+        self.entry_ref_prehashed(hashed, key).or_default()
+    }
+
+    fn iter<'m>(&'m self) -> impl Iterator<Item = (&'m K, &'m V)>
+    where
+        K: 'm,
+        V: 'm,
+    {
+        StackMap::iter(self)
+    }
+
+    fn is_clustered(&self) -> bool {
+        StackMap::is_clustered(self)
+    }
+
+    fn rehash(&mut self, hasher: H) {
+        StackMap::rehash(self, hasher)
+    }
+
+    fn merge_all<'m, B>(maps: &'m [B], n_threads: usize) -> Vec<(&'m K, V)>
+    where
+        B: Borrow<Self> + Sync,
+        Self: 'm,
+        K: Sync + 'm,
+        V: Merge + Clone + Send + Sync + 'm,
+    {
+        merge::par_merge(maps, n_threads)
+    }
+}
+
+impl<K, V, H> AggMap<K, V> for HashMap<K, V, H>
+where
+    K: Borrow<[u8]> + Hash + Ord,
+    V: Default,
+    H: BuildHasher,
+{
+    type Hasher = H;
+
+    fn new_with_hasher(hasher: H) -> Self {
+        HashMap::with_capacity_and_hasher(CAPACITY, hasher)
+    }
+
+    fn hasher(&self) -> &H {
+        HashMap::hasher(self)
+    }
+
+    /// std has no way to pass the hash in, and `entry` wants an owned key
+    #[inline]
+    fn get_or_default<'b>(&mut self, _: u64, key: &'b [u8]) -> &mut V
+    where
+        K: From<&'b [u8]>,
+    {
+        self.entry(key.into()).or_default()
+    }
+
+    fn iter<'m>(&'m self) -> impl Iterator<Item = (&'m K, &'m V)>
+    where
+        K: 'm,
+        V: 'm,
+    {
+        HashMap::iter(self)
+    }
+}
+
+/// hashbrown's raw table, which takes the hashes from the parser. They are
+/// stored along the entries to grow without hashing again.
+pub struct TableMap<K, V, H> {
+    table: HashTable<(u64, K, V)>,
+    hasher: H,
+}
+
+impl<K, V, H> AggMap<K, V> for TableMap<K, V, H>
+where
+    K: Borrow<[u8]> + Hash + Ord,
+    V: Default,
+    H: BuildHasher,
+{
+    type Hasher = H;
+
+    fn new_with_hasher(hasher: H) -> Self {
+        Self {
+            table: HashTable::with_capacity(CAPACITY),
+            hasher,
+        }
+    }
+
+    fn hasher(&self) -> &H {
+        &self.hasher
+    }
+
+    #[inline]
+    fn get_or_default<'b>(&mut self, hashed: u64, key: &'b [u8]) -> &mut V
+    where
+        K: From<&'b [u8]>,
+    {
+        let entry = self.table.entry(
+            hashed,
+            |(h, k, _)| *h == hashed && k.borrow() == key,
+            |(h, _, _)| *h,
+        );
+        match entry {
+            Entry::Occupied(entry) => &mut entry.into_mut().2,
+            Entry::Vacant(entry) => {
+                &mut entry
+                    .insert((hashed, key.into(), V::default()))
+                    .into_mut()
+                    .2
+            }
+        }
+    }
+
+    fn iter<'m>(&'m self) -> impl Iterator<Item = (&'m K, &'m V)>
+    where
+        K: 'm,
+        V: 'm,
+    {
+        self.table.iter().map(|(_, k, v)| (k, v))
+    }
+}
+
+/// `intmap` keyed by the hashes from the parser, the names sharing a hash
+/// are told apart in a small list
+pub struct IntKeyMap<K, V, H> {
+    map: IntMap<u64, SmallVec<[(K, V); 1]>>,
+    hasher: H,
+}
+
+impl<K, V, H> AggMap<K, V> for IntKeyMap<K, V, H>
+where
+    K: Borrow<[u8]> + Hash + Ord,
+    V: Default,
+    H: BuildHasher,
+{
+    type Hasher = H;
+
+    fn new_with_hasher(hasher: H) -> Self {
+        Self {
+            map: IntMap::with_capacity(CAPACITY),
+            hasher,
+        }
+    }
+
+    fn hasher(&self) -> &H {
+        &self.hasher
+    }
+
+    #[inline]
+    fn get_or_default<'b>(&mut self, hashed: u64, key: &'b [u8]) -> &mut V
+    where
+        K: From<&'b [u8]>,
+    {
+        let same_hash = self.map.entry(hashed).or_default();
+        let idx = match same_hash.iter().position(|(k, _)| k.borrow() == key) {
+            Some(idx) => idx,
+            None => {
+                same_hash.push((key.into(), V::default()));
+                same_hash.len() - 1
+            }
+        };
+        &mut same_hash[idx].1
+    }
+
+    fn iter<'m>(&'m self) -> impl Iterator<Item = (&'m K, &'m V)>
+    where
+        K: 'm,
+        V: 'm,
+    {
+        self.map
+            .values()
+            .flat_map(|same_hash| same_hash.iter().map(|(k, v)| (k, v)))
+    }
+}
+
+impl<K, V, H> HashStat for TableMap<K, V, H> {
+    type Stats = CapacityStats;
+
+    fn hash_stats(&self) -> CapacityStats {
+        CapacityStats {
+            keys: self.table.len(),
+            capacity: self.table.capacity(),
+        }
+    }
+}
+
+/// The chains of an [`IntKeyMap`]: `intmap` chains the hashes that land in
+/// the same slot, and each hash lists the names that share it
+#[derive(Debug, Clone, Copy)]
+pub struct ChainStats {
+    pub keys: usize,
+    pub slots: usize,
+    /// hashes in a slot that already held another one
+    pub slot_collisions: usize,
+    /// names sharing their full hash with an earlier one
+    pub full_collisions: usize,
+}
+
+impl Display for ChainStats {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "keys={} slots={} slot_collisions={} full_collisions={}",
+            self.keys, self.slots, self.slot_collisions, self.full_collisions
+        )
+    }
+}
+
+impl<K, V, H> HashStat for IntKeyMap<K, V, H> {
+    type Stats = ChainStats;
+
+    fn hash_stats(&self) -> ChainStats {
+        let hashes = self.map.len();
+        let keys = self.map.values().map(|same_hash| same_hash.len()).sum();
+        ChainStats {
+            keys,
+            slots: self.map.capacity(),
+            slot_collisions: hashes - self.map.load() as usize,
+            full_collisions: keys - hashes,
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::{collections::HashMap, hash::BuildHasher};
+
+    use rustc_hash::FxBuildHasher;
+
+    use super::{AggMap, CAPACITY, IntKeyMap, TableMap};
+    use crate::{
+        aggregator::Aggregator,
+        hash_quality::HashStat,
+        hashmap::{StackMap, test::on_big_stack},
+        rng::{SplitMix64, random_names},
+        stats::Stat,
+    };
+
+    /// hashes everything to 0, so that every name collides
+    #[derive(Clone, Default)]
+    struct Zero;
+
+    impl BuildHasher for Zero {
+        type Hasher = Zero;
+
+        fn build_hasher(&self) -> Self::Hasher {
+            Zero
+        }
+    }
+
+    impl std::hash::Hasher for Zero {
+        fn finish(&self) -> u64 {
+            0
+        }
+
+        fn write(&mut self, _: &[u8]) {}
+    }
+
+    /// Splits the same rows over a few maps, merges them, and compares with
+    /// the std map
+    fn same_as_std<'a, M, H>(names: &'a [Vec<u8>], hasher: H)
+    where
+        M: AggMap<&'a [u8], Stat, Hasher = H> + Sync,
+        H: BuildHasher + Clone,
+    {
+        let mut expected: HashMap<&[u8], Stat> = HashMap::new();
+        let mut maps: Vec<_> = (0..3).map(|_| M::new_with_hasher(hasher.clone())).collect();
+        let mut rng = SplitMix64::new(5);
+        for row in 0..4 * names.len() {
+            let name = names[rng.below(names.len() as u64) as usize].as_slice();
+            let t = rng.below(1999) as i16 - 999;
+            for stat in [
+                expected.entry(name).or_default(),
+                maps[row % 3].get_or_default(hasher.hash_one(name), name),
+            ] {
+                stat.update(t);
+            }
+        }
+
+        let mut expected: Vec<_> = expected.into_iter().collect();
+        expected.sort_unstable_by_key(|(k, _)| *k);
+        let merged: Vec<_> = M::merge_all(&maps, 2)
+            .into_iter()
+            .map(|(k, v)| (*k, v))
+            .collect();
+        assert_eq!(merged, expected);
+    }
+
+    fn all_backends<H: BuildHasher + Clone + Sync>(hasher: H) {
+        let names = random_names(&mut SplitMix64::new(1), 500);
+        same_as_std::<StackMap<_, _, H>, _>(&names, hasher.clone());
+        same_as_std::<HashMap<_, _, H>, _>(&names, hasher.clone());
+        same_as_std::<TableMap<_, _, H>, _>(&names, hasher.clone());
+        same_as_std::<IntKeyMap<_, _, H>, _>(&names, hasher);
+    }
+
+    #[test]
+    fn backends_agree() {
+        on_big_stack(|| all_backends(FxBuildHasher))
+    }
+
+    #[test]
+    fn backends_agree_when_everything_collides() {
+        on_big_stack(|| all_backends(Zero))
+    }
+
+    /// `--stats` of the heap backends are their own numbers
+    #[test]
+    fn heap_backends_stats() {
+        let names = random_names(&mut SplitMix64::new(2), 100);
+        let mut std = HashMap::<&[u8], Stat, _>::new_with_hasher(FxBuildHasher);
+        let mut table = TableMap::<&[u8], Stat, _>::new_with_hasher(FxBuildHasher);
+        let mut int = IntKeyMap::<&[u8], Stat, _>::new_with_hasher(Zero);
+        for name in &names {
+            let hashed = FxBuildHasher.hash_one(name.as_slice());
+            std.get_or_default(hashed, name);
+            table.get_or_default(hashed, name);
+            int.get_or_default(0, name);
+        }
+        for stats in [std.hash_stats(), table.hash_stats()] {
+            assert_eq!(stats.keys, 100);
+            assert!(stats.capacity >= CAPACITY, "{stats}");
+        }
+        // a single hash, so a single slot
+        let int = int.hash_stats();
+        assert_eq!(
+            (int.keys, int.slot_collisions, int.full_collisions),
+            (100, 0, 99)
+        );
+    }
+}
@@ -0,0 +1,249 @@
+//! Times this binary and the other implementations of the flake on the same
+//! file, and checks that they print the same result, see `bench --help`
+
+use std::{
+    env,
+    fs::File,
+    io::{self, Read},
+    os::unix::process::ExitStatusExt,
+    path::{Path, PathBuf},
+    process::{self, ExitStatus, Stdio},
+    thread,
+    time::{Duration, Instant},
+};
+
+use crate::{FILE, cli::BenchArgs};
+
+/// `(name, script, banner)` of the implementations the dev shell of the flake
+/// puts on the `PATH`. The scripts print `banner` before the result.
+///
+/// The Java ones sort the stations in a `TreeMap`, by UTF-16 code units,
+/// where this binary sorts by bytes. The orders differ when a name has a
+/// character above the BMP where another has one from U+E000 to U+FFFF, the
+/// results of such files are reported as different.
+///
+/// The baseline also rounds the means in doubles. On some ties, like an exact
+/// mean of -99.85, it lands just below and prints -99.9 where this binary
+/// rounds half up to -99.8 (see `fixtures/rounding`), another reported
+/// difference.
+static BASELINES: &[(&str, &str, &str)] = &[
+    (
+        "java-baseline",
+        "run-solution",
+        "Running Java Baseline Solution...",
+    ),
+    (
+        "java-thomaswue",
+        "run-fastest-java",
+        "Running Java Baseline Solution...",
+    ),
+    (
+        "cpp-friend",
+        "run-friend",
+        "Running friends Optimized C++ Solution...",
+    ),
+];
+
+struct Run {
+    elapsed: Duration,
+    /// in bytes, of the process and the ones it waited for
+    peak_rss: u64,
+    /// the whole output, without the banner
+    result: String,
+}
+
+/// Prints a line of timings per implementation, `Ok(false)` if they didn't
+/// all print the same result
+pub fn bench(args: &BenchArgs) -> io::Result<bool> {
+    let mut impls = vec![(
+        "rust".to_string(),
+        vec![env::current_exe()?.display().to_string()],
+        None,
+    )];
+    if FILE != "measurements.txt" {
+        eprintln!("note: this binary was built with FILE={FILE}, it reads that file");
+    }
+    for &(name, script, banner) in BASELINES {
+        match on_path(script) {
+            Some(path) => impls.push((
+                name.to_string(),
+                vec![path.display().to_string()],
+                Some(banner),
+            )),
+            None => eprintln!("note: `{script}` is not on the PATH, skipping {name}"),
+        }
+    }
+    impls.extend(
+        args.impls
+            .iter()
+            .map(|(name, c)| (name.clone(), c.clone(), None)),
+    );
+
+    // read once, so that every implementation finds it in the page cache
+    let file = args.dir.join("measurements.txt");
+    io::copy(&mut File::open(&file)?, &mut io::sink())?;
+
+    println!(
+        "{:<16} {:>10} {:>10} {:>12}",
+        "", "median", "stddev", "peak rss"
+    );
+    let mut expected = None;
+    let mut same = true;
+    for (name, command, banner) in &impls {
+        let runs = (0..args.runs)
+            .map(|_| run(command, &args.dir, *banner))
+            .collect::<io::Result<Vec<_>>>()
+            .map_err(|e| io::Error::other(format!("{name}: {e}")))?;
+
+        let times: Vec<_> = runs.iter().map(|r| r.elapsed.as_secs_f64()).collect();
+        let peak_rss = runs.iter().map(|r| r.peak_rss).max().unwrap();
+        println!(
+            "{name:<16} {:>8.3} s {:>8.3} s {:>8.1} MiB",
+            median(&times),
+            stddev(&times),
+            peak_rss as f64 / (1 << 20) as f64
+        );
+
+        // the first run of the first implementation is the reference
+        let expected = expected.get_or_insert_with(|| runs[0].result.clone());
+        if runs.iter().any(|r| r.result != *expected) {
+            eprintln!("error: {name} printed another result than {}", impls[0].0);
+            same = false;
+        }
+    }
+    if same {
+        println!("the results are identical");
+    }
+    Ok(same)
+}
+
+/// The first `program` in the directories of the `PATH`
+fn on_path(program: &str) -> Option<PathBuf> {
+    env::split_paths(&env::var_os("PATH")?)
+        .map(|dir| dir.join(program))
+        .find(|path| path.is_file())
+}
+
+/// Runs `command` in `dir`, an error if it fails. `banner` is the line it
+/// prints before its result, if any.
+fn run(command: &[String], dir: &Path, banner: Option<&str>) -> io::Result<Run> {
+    let start = Instant::now();
+    let mut child = process::Command::new(&command[0])
+        .args(&command[1..])
+        .current_dir(dir)
+        .stdin(Stdio::null())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()?;
+
+    // both pipes are drained, the child would block on a full one
+    let mut stderr = child.stderr.take().unwrap();
+    let errors = thread::spawn(move || {
+        let mut errors = vec![];
+        stderr.read_to_end(&mut errors).map(|_| errors)
+    });
+    let mut out = vec![];
+    child.stdout.take().unwrap().read_to_end(&mut out)?;
+    let (status, peak_rss) = wait(child.id())?;
+    let elapsed = start.elapsed();
+
+    let errors = errors.join().unwrap()?;
+    if !status.success() {
+        return Err(io::Error::other(format!(
+            "`{}` failed ({status}): {}",
+            command.join(" "),
+            String::from_utf8_lossy(&errors).trim()
+        )));
+    }
+    let out = String::from_utf8_lossy(&out);
+    let result = banner
+        .and_then(|banner| out.strip_prefix(banner)?.strip_prefix('\n'))
+        .unwrap_or(&out)
+        .to_string();
+    Ok(Run {
+        elapsed,
+        peak_rss,
+        result,
+    })
+}
+
+/// Reaps the child `pid`, with its peak RSS in bytes: `Child::wait` drops
+/// the resource usage
+fn wait(pid: u32) -> io::Result<(ExitStatus, u64)> {
+    let mut status = 0;
+    // safety: all zeros is a valid `rusage`
+    let mut usage: libc::rusage = unsafe { std::mem::zeroed() };
+    // safety: the pointers are valid for the call
+    while unsafe { libc::wait4(pid as libc::pid_t, &mut status, 0, &mut usage) } == -1 {
+        let e = io::Error::last_os_error();
+        if e.kind() != io::ErrorKind::Interrupted {
+            return Err(e);
+        }
+    }
+    // kilobytes on linux, bytes on macos
+    let unit = if cfg!(target_os = "macos") { 1 } else { 1024 };
+    Ok((ExitStatus::from_raw(status), usage.ru_maxrss as u64 * unit))
+}
+
+fn median(xs: &[f64]) -> f64 {
+    let mut xs = xs.to_vec();
+    xs.sort_by(f64::total_cmp);
+    let mid = xs.len() / 2;
+    if xs.len() % 2 == 1 {
+        xs[mid]
+    } else {
+        (xs[mid - 1] + xs[mid]) / 2.
+    }
+}
+
+/// Sample standard deviation, 0 for a single value
+fn stddev(xs: &[f64]) -> f64 {
+    if xs.len() < 2 {
+        return 0.;
+    }
+    let n = xs.len() as f64;
+    let mean = xs.iter().sum::<f64>() / n;
+    (xs.iter().map(|x| (x - mean).powi(2)).sum::<f64>() / (n - 1.)).sqrt()
+}
+
+#[cfg(test)]
+mod test {
+    use super::{median, run, stddev};
+
+    #[test]
+    fn statistics() {
+        assert_eq!(median(&[3., 1., 2.]), 2.);
+        assert_eq!(median(&[4., 1., 3., 2.]), 2.5);
+        assert_eq!(stddev(&[1.]), 0.);
+        assert_eq!(
+            stddev(&[2., 4., 4., 4., 5., 5., 7., 9.]),
+            (32f64 / 7.).sqrt()
+        );
+    }
+
+    #[test]
+    fn runs_commands() {
+        let sh = |script: &str| ["sh", "-c", script].map(String::from);
+        let script = sh("echo banner; echo '{a=1.0/1.0/1.0}'; echo noise >&2");
+        let done = run(&script, ".".as_ref(), Some("banner")).unwrap();
+        assert_eq!(done.result, "{a=1.0/1.0/1.0}\n");
+        assert!(done.peak_rss > 0);
+
+        // only the known banner is dropped, the rest of the output counts
+        let done = run(&script, ".".as_ref(), None).unwrap();
+        assert_eq!(done.result, "banner\n{a=1.0/1.0/1.0}\n");
+        let done = run(
+            &sh("echo '{a=1.0/1.0/1.0}'; echo extra"),
+            ".".as_ref(),
+            Some("banner"),
+        );
+        assert_eq!(done.unwrap().result, "{a=1.0/1.0/1.0}\nextra\n");
+
+        let failed = run(&sh("echo oops >&2; exit 3"), ".".as_ref(), None);
+        let message = failed.err().unwrap().to_string();
+        assert!(
+            message.contains("oops") && message.contains('3'),
+            "{message}"
+        );
+    }
+}
@@ -0,0 +1,412 @@
+//! Command line flags
+
+use std::{fmt::Display, path::PathBuf, process::exit, str::FromStr};
+
+use crate::{generate::MAX_STATIONS, stations::STATIONS};
+
+static USAGE: &str = "\
+usage: one_billion_row_challenge_rust [options]
+       one_billion_row_challenge_rust generate <rows> [generate options]
+       one_billion_row_challenge_rust bench [bench options]
+
+options:
+    --hasher <fx|station|seeded>
+                            hasher of the maps (default: the `MHasher` alias),
+                            `seeded` is randomized, for untrusted input
+    --backend <stack|std|hashbrown|intmap>
+                            map the threads aggregate into (default: stack)
+    --scanner <swar|sse2|x86-64-v3|avx2|avx512|avx512-blocks|neon>
+                            line scanner (default: the fastest this cpu has)
+    --batch                 parse a few parts of each thread's lines in
+                            lockstep
+    --stats                 print diagnostics about the threads and their
+                            maps to stderr, as `key=value` lines
+    -h, --help              print this message
+
+generate options:
+    --out <path>            file to write (default: measurements.txt)
+    --seed <n>              seed of the random generator (default: 0)
+    --stations <n>          number of stations, at most 10000 (default: 413)
+    --names <official|random|long|unicode>
+                            names of the stations (default: official, the
+                            413 of the reference generator), `long` ones
+                            are 100 bytes, `unicode` ones multi-byte
+    --extreme               temperatures spread over the whole -99.9..99.9
+                            range, many of them at its ends
+    --threads <n>           writing threads (default: all the cpus)
+
+bench options:
+    --runs <n>              timed runs of each implementation (default: 5)
+    --dir <path>            where the implementations find measurements.txt
+                            (default: .)
+    --impl <name>=<command>
+                            also time `command`, split on spaces, can be
+                            repeated. This binary and the baselines of the
+                            flake found on the PATH are always timed.
+
+environment:
+    NUM_CPU                 number of worker threads (default: all the cpus)";
+
+/// Hashers that can be picked at runtime
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum HasherKind {
+    Fx,
+    Station,
+    Seeded,
+}
+
+impl FromStr for HasherKind {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "fx" => Ok(Self::Fx),
+            "station" => Ok(Self::Station),
+            "seeded" => Ok(Self::Seeded),
+            _ => Err(format!("unknown hasher `{s}`")),
+        }
+    }
+}
+
+/// Maps that can be picked at runtime, see `backend`
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum BackendKind {
+    #[default]
+    Stack,
+    Std,
+    Hashbrown,
+    Intmap,
+}
+
+impl FromStr for BackendKind {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "stack" => Ok(Self::Stack),
+            "std" => Ok(Self::Std),
+            "hashbrown" => Ok(Self::Hashbrown),
+            "intmap" => Ok(Self::Intmap),
+            _ => Err(format!("unknown backend `{s}`")),
+        }
+    }
+}
+
+/// Line scanners that can be picked at runtime, see `parser::Scanner`. Only
+/// the ones of the target and the cpu are available.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ScannerKind {
+    Swar,
+    Sse2,
+    X86V3,
+    Avx2,
+    Avx512,
+    Avx512Blocks,
+    Neon,
+}
+
+impl FromStr for ScannerKind {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "swar" => Ok(Self::Swar),
+            "sse2" => Ok(Self::Sse2),
+            "x86-64-v3" => Ok(Self::X86V3),
+            "avx2" => Ok(Self::Avx2),
+            "avx512" => Ok(Self::Avx512),
+            "avx512-blocks" => Ok(Self::Avx512Blocks),
+            "neon" => Ok(Self::Neon),
+            _ => Err(format!("unknown scanner `{s}`")),
+        }
+    }
+}
+
+/// Station names `generate` can write
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum NameKind {
+    #[default]
+    Official,
+    Random,
+    Long,
+    Unicode,
+}
+
+impl FromStr for NameKind {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "official" => Ok(Self::Official),
+            "random" => Ok(Self::Random),
+            "long" => Ok(Self::Long),
+            "unicode" => Ok(Self::Unicode),
+            _ => Err(format!("unknown names `{s}`")),
+        }
+    }
+}
+
+/// What to do, picked by the first argument
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Command {
+    /// Aggregate the measurements
+    Run(Args),
+    /// Write a measurements file
+    Generate(GenerateArgs),
+    /// Time the implementations
+    Bench(BenchArgs),
+}
+
+impl Command {
+    /// Parses the process arguments, exits with the usage on error
+    pub fn parse() -> Self {
+        match Self::parse_from(std::env::args().skip(1)) {
+            Ok(Some(command)) => command,
+            Ok(None) => {
+                println!("{USAGE}");
+                exit(0)
+            }
+            Err(e) => {
+                eprintln!("error: {e}\n\n{USAGE}");
+                exit(2)
+            }
+        }
+    }
+
+    /// `Ok(None)` when the help was asked for
+    pub fn parse_from(args: impl IntoIterator<Item = String>) -> Result<Option<Self>, String> {
+        let mut args = args.into_iter().peekable();
+        if args.next_if(|arg| arg == "generate").is_some() {
+            Ok(GenerateArgs::parse_from(args)?.map(Self::Generate))
+        } else if args.next_if(|arg| arg == "bench").is_some() {
+            Ok(BenchArgs::parse_from(args)?.map(Self::Bench))
+        } else {
+            Ok(Args::parse_from(args)?.map(Self::Run))
+        }
+    }
+}
+
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub struct Args {
+    /// `None` for the compile time default
+    pub hasher: Option<HasherKind>,
+    pub backend: BackendKind,
+    /// `None` for the fastest available
+    pub scanner: Option<ScannerKind>,
+    pub batch: bool,
+    pub stats: bool,
+}
+
+impl Args {
+    /// `Ok(None)` when the help was asked for
+    pub fn parse_from(args: impl IntoIterator<Item = String>) -> Result<Option<Self>, String> {
+        let mut ret = Self::default();
+        let mut args = args.into_iter();
+        while let Some(arg) = args.next() {
+            match arg.as_str() {
+                "--hasher" => ret.hasher = Some(value(&arg, args.next())?),
+                "--backend" => ret.backend = value(&arg, args.next())?,
+                "--scanner" => ret.scanner = Some(value(&arg, args.next())?),
+                "--batch" => ret.batch = true,
+                "--stats" => ret.stats = true,
+                "-h" | "--help" => return Ok(None),
+                _ => return Err(format!("unexpected argument `{arg}`")),
+            }
+        }
+        Ok(Some(ret))
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct GenerateArgs {
+    pub rows: u64,
+    pub out: PathBuf,
+    pub seed: u64,
+    pub stations: usize,
+    pub names: NameKind,
+    pub extreme: bool,
+    /// `None` for all the cpus
+    pub threads: Option<usize>,
+}
+
+impl GenerateArgs {
+    /// The arguments after `generate`, `Ok(None)` when the help was asked for
+    pub fn parse_from(args: impl IntoIterator<Item = String>) -> Result<Option<Self>, String> {
+        let mut rows = None;
+        let mut ret = Self {
+            rows: 0,
+            out: "measurements.txt".into(),
+            seed: 0,
+            stations: STATIONS.len(),
+            names: NameKind::Official,
+            extreme: false,
+            threads: None,
+        };
+        let mut args = args.into_iter();
+        while let Some(arg) = args.next() {
+            match arg.as_str() {
+                "--out" => ret.out = value(&arg, args.next())?,
+                "--seed" => ret.seed = value(&arg, args.next())?,
+                "--stations" => ret.stations = value(&arg, args.next())?,
+                "--names" => ret.names = value(&arg, args.next())?,
+                "--extreme" => ret.extreme = true,
+                "--threads" => ret.threads = Some(value(&arg, args.next())?),
+                "-h" | "--help" => return Ok(None),
+                _ if rows.is_none() && !arg.starts_with('-') => {
+                    rows = Some(value("<rows>", Some(arg))?)
+                }
+                _ => return Err(format!("unexpected argument `{arg}`")),
+            }
+        }
+        ret.rows = rows.ok_or("missing the number of rows")?;
+
+        let max_stations = match ret.names {
+            NameKind::Official => STATIONS.len(),
+            _ => MAX_STATIONS,
+        };
+        if !(1..=max_stations).contains(&ret.stations) {
+            return Err(format!(
+                "--stations: between 1 and {max_stations} for these names"
+            ));
+        }
+        if ret.threads == Some(0) {
+            return Err("--threads: at least 1".into());
+        }
+        Ok(Some(ret))
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct BenchArgs {
+    pub runs: usize,
+    pub dir: PathBuf,
+    /// `(name, program and arguments)` of the `--impl`s
+    pub impls: Vec<(String, Vec<String>)>,
+}
+
+impl BenchArgs {
+    /// The arguments after `bench`, `Ok(None)` when the help was asked for
+    pub fn parse_from(args: impl IntoIterator<Item = String>) -> Result<Option<Self>, String> {
+        let mut ret = Self {
+            runs: 5,
+            dir: ".".into(),
+            impls: vec![],
+        };
+        let mut args = args.into_iter();
+        while let Some(arg) = args.next() {
+            match arg.as_str() {
+                "--runs" => ret.runs = value(&arg, args.next())?,
+                "--dir" => ret.dir = value(&arg, args.next())?,
+                "--impl" => {
+                    let spec: String = value(&arg, args.next())?;
+                    let (name, command) = spec
+                        .split_once('=')
+                        .ok_or_else(|| format!("--impl: expected `name=command`, got `{spec}`"))?;
+                    let command: Vec<_> = command.split_whitespace().map(String::from).collect();
+                    if command.is_empty() {
+                        return Err(format!("--impl: empty command for `{name}`"));
+                    }
+                    ret.impls.push((name.to_string(), command));
+                }
+                "-h" | "--help" => return Ok(None),
+                _ => return Err(format!("unexpected argument `{arg}`")),
+            }
+        }
+        if ret.runs == 0 {
+            return Err("--runs: at least 1".into());
+        }
+        Ok(Some(ret))
+    }
+}
+
+fn value<T: FromStr<Err: Display>>(flag: &str, value: Option<String>) -> Result<T, String> {
+    let value = value.ok_or_else(|| format!("missing value for `{flag}`"))?;
+    value.parse().map_err(|e| format!("{flag}: {e}"))
+}
+
+#[cfg(test)]
+mod test {
+    use super::{Args, BackendKind, Command, HasherKind, NameKind, ScannerKind};
+
+    fn parse(args: &[&str]) -> Result<Option<Args>, String> {
+        Args::parse_from(args.iter().map(|s| s.to_string()))
+    }
+
+    #[test]
+    fn parse_flags() {
+        assert_eq!(parse(&[]), Ok(Some(Args::default())));
+        assert_eq!(
+            parse(&["--hasher", "station"]).unwrap().unwrap().hasher,
+            Some(HasherKind::Station)
+        );
+        assert!(parse(&["--stats"]).unwrap().unwrap().stats);
+        assert!(parse(&["--batch"]).unwrap().unwrap().batch);
+        assert_eq!(
+            parse(&["--backend", "hashbrown"]).unwrap().unwrap().backend,
+            BackendKind::Hashbrown
+        );
+        assert_eq!(
+            parse(&["--scanner", "avx2"]).unwrap().unwrap().scanner,
+            Some(ScannerKind::Avx2)
+        );
+        assert_eq!(parse(&["--help"]), Ok(None));
+        assert!(parse(&["--hasher"]).is_err());
+        assert!(parse(&["--hasher", "sip"]).is_err());
+        assert!(parse(&["--scanner", "mmx"]).is_err());
+        assert!(parse(&["--nope"]).is_err());
+    }
+
+    #[test]
+    fn parse_generate() {
+        let parse = |args: &[&str]| Command::parse_from(args.iter().map(|s| s.to_string()));
+        let Ok(Some(Command::Generate(args))) = parse(&[
+            "generate",
+            "1000",
+            "--names",
+            "unicode",
+            "--stations",
+            "10000",
+        ]) else {
+            panic!()
+        };
+        assert_eq!(
+            (args.rows, args.names, args.stations),
+            (1000, NameKind::Unicode, 10_000)
+        );
+        assert_eq!(args.out.to_str(), Some("measurements.txt"));
+        assert_eq!(
+            parse(&["--stats"]).unwrap().unwrap(),
+            Command::Run(Args {
+                stats: true,
+                ..Args::default()
+            })
+        );
+        assert_eq!(parse(&["generate", "--help"]), Ok(None));
+        assert!(parse(&["generate"]).is_err());
+        assert!(parse(&["generate", "10", "20"]).is_err());
+        assert!(parse(&["generate", "10", "--stations", "414"]).is_err());
+        assert!(parse(&["generate", "10", "--names", "long", "--stations", "10001"]).is_err());
+        assert!(parse(&["generate", "10", "--threads", "0"]).is_err());
+    }
+
+    #[test]
+    fn parse_bench() {
+        let parse = |args: &[&str]| Command::parse_from(args.iter().map(|s| s.to_string()));
+        let Ok(Some(Command::Bench(args))) =
+            parse(&["bench", "--runs", "3", "--impl", "batch=./brc --batch"])
+        else {
+            panic!()
+        };
+        assert_eq!(args.runs, 3);
+        assert_eq!(
+            args.impls,
+            [(
+                "batch".to_string(),
+                vec!["./brc".to_string(), "--batch".to_string()]
+            )]
+        );
+        assert!(parse(&["bench", "--impl", "nameless"]).is_err());
+        assert!(parse(&["bench", "--impl", "empty= "]).is_err());
+        assert!(parse(&["bench", "--runs", "0"]).is_err());
+    }
+}
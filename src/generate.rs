@@ -0,0 +1,240 @@
+//! Writes measurements files that follow the rules of the challenge, like the
+//! reference `CreateMeasurements` does, see `generate --help`
+
+use std::{
+    collections::HashSet,
+    f64::consts::TAU,
+    fs::File,
+    io::{self, BufWriter, Write},
+    sync::mpsc::sync_channel,
+    thread,
+};
+
+use crate::{
+    cli::{GenerateArgs, NameKind},
+    rng::{SplitMix64, random_names},
+    stations::STATIONS,
+};
+
+/// Most distinct stations the rules allow
+pub const MAX_STATIONS: usize = 10_000;
+
+/// Rows rendered at once by a thread, ~1 MB of the official stations
+const BLOCK_ROWS: u64 = 1 << 16;
+
+/// Writes the file of `args`
+pub fn generate(args: &GenerateArgs) -> io::Result<()> {
+    let threads = args.threads.unwrap_or_else(num_cpus::get);
+    let out = File::create(&args.out)?;
+    write_rows(args, threads, BufWriter::new(out))
+}
+
+/// Writes the rows of `args` to `out`, rendered by `threads` threads. Each
+/// block of rows has its own generator, so the rows don't depend on
+/// `threads`.
+pub fn write_rows(args: &GenerateArgs, threads: usize, mut out: impl Write) -> io::Result<()> {
+    let stations = stations(args);
+    let blocks = args.rows.div_ceil(BLOCK_ROWS);
+    thread::scope(|sc| {
+        let rendered: Vec<_> = (0..threads)
+            .map(|first| {
+                // a few blocks ahead of the writer at most
+                let (tx, rx) = sync_channel(2);
+                let stations = &stations;
+                sc.spawn(move || {
+                    for block in (first as u64..blocks).step_by(threads) {
+                        // the writer is gone after an error
+                        if tx.send(render(args, stations, block)).is_err() {
+                            break;
+                        }
+                    }
+                });
+                rx
+            })
+            .collect();
+        for block in 0..blocks {
+            let rows = rendered[(block % threads as u64) as usize].recv().unwrap();
+            out.write_all(&rows)?;
+        }
+        out.flush()
+    })
+}
+
+/// `(name, mean temperature)` of the stations of `args`
+fn stations(args: &GenerateArgs) -> Vec<(Vec<u8>, f64)> {
+    let rng = &mut SplitMix64::new(args.seed);
+    let names = match args.names {
+        NameKind::Official => {
+            return STATIONS[..args.stations]
+                .iter()
+                .map(|&(name, mean)| (name.as_bytes().to_vec(), mean))
+                .collect();
+        }
+        NameKind::Random => random_names(rng, args.stations),
+        NameKind::Long => distinct(rng, args.stations, |rng| {
+            static ALPHABET: &[u8] = b"abcdefghijklmnopqrstuvwxyzABCDEFGHIJKLMNOPQRSTUVWXYZ -'.";
+            (0..100)
+                .map(|_| ALPHABET[rng.below(ALPHABET.len() as u64) as usize])
+                .collect()
+        }),
+        NameKind::Unicode => distinct(rng, args.stations, |rng| {
+            // 1 to 4 bytes each, so that 25 of them fit in the 100 bytes
+            static CHARS: &[char] = &[
+                'a', 'Z', ' ', 'é', 'ß', 'ø', 'Ω', 'ж', 'ש', 'ع', '東', '京', 'ሰ', '€', '😀', '🌧',
+            ];
+            (0..1 + rng.below(25))
+                .map(|_| CHARS[rng.below(CHARS.len() as u64) as usize])
+                .collect::<String>()
+                .into_bytes()
+        }),
+    };
+    // the official means are between -17 and 30
+    names
+        .into_iter()
+        .map(|name| (name, uniform(rng) * 50. - 20.))
+        .collect()
+}
+
+/// `n` distinct names from `name`, in the order they were made
+fn distinct(
+    rng: &mut SplitMix64,
+    n: usize,
+    mut name: impl FnMut(&mut SplitMix64) -> Vec<u8>,
+) -> Vec<Vec<u8>> {
+    let mut seen = HashSet::with_capacity(n);
+    let mut names = Vec::with_capacity(n);
+    while names.len() < n {
+        let name = name(rng);
+        if seen.insert(name.clone()) {
+            names.push(name);
+        }
+    }
+    names
+}
+
+/// The rows of `block`, lines of `name;-12.3`
+fn render(args: &GenerateArgs, stations: &[(Vec<u8>, f64)], block: u64) -> Vec<u8> {
+    // the starting states are mixed, so the blocks don't share a sequence
+    let rng = &mut SplitMix64::new(SplitMix64::new(args.seed ^ block.rotate_left(32)).next_u64());
+    let rows = BLOCK_ROWS.min(args.rows - block * BLOCK_ROWS);
+    let mut out = Vec::with_capacity(rows as usize * 16);
+    for _ in 0..rows {
+        let (name, mean) = &stations[rng.below(stations.len() as u64) as usize];
+        let t = temperature(rng, *mean, args.extreme);
+        out.extend_from_slice(name);
+        out.push(b';');
+        if t < 0 {
+            out.push(b'-');
+        }
+        let t = t.unsigned_abs();
+        if t >= 100 {
+            out.push(b'0' + (t / 100) as u8);
+        }
+        out.extend_from_slice(&[
+            b'0' + (t / 10 % 10) as u8,
+            b'.',
+            b'0' + (t % 10) as u8,
+            b'\n',
+        ]);
+    }
+    out
+}
+
+/// A temperature in tenths, around `mean` like the reference generator, or
+/// anywhere in the range and often at its ends when `extreme`
+fn temperature(rng: &mut SplitMix64, mean: f64, extreme: bool) -> i16 {
+    if extreme {
+        match rng.below(8) {
+            0 => -999,
+            1 => 999,
+            _ => rng.below(1999) as i16 - 999,
+        }
+    } else {
+        (gaussian(rng, mean, 10.) * 10.).round().clamp(-999., 999.) as i16
+    }
+}
+
+/// Uniform in `0..1`
+fn uniform(rng: &mut SplitMix64) -> f64 {
+    (rng.next_u64() >> 11) as f64 / (1u64 << 53) as f64
+}
+
+/// Box-Muller transform
+fn gaussian(rng: &mut SplitMix64, mean: f64, sd: f64) -> f64 {
+    let (u, v) = (1. - uniform(rng), uniform(rng));
+    mean + sd * (-2. * u.ln()).sqrt() * (TAU * v).cos()
+}
+
+#[cfg(test)]
+mod test {
+    use std::collections::HashSet;
+
+    use super::{BLOCK_ROWS, write_rows};
+    use crate::{
+        cli::{GenerateArgs, NameKind},
+        reference,
+    };
+
+    fn args(rows: u64, names: NameKind, stations: usize, extreme: bool) -> GenerateArgs {
+        GenerateArgs {
+            rows,
+            out: "unused".into(),
+            seed: 7,
+            stations,
+            names,
+            extreme,
+            threads: None,
+        }
+    }
+
+    fn rows(args: &GenerateArgs, threads: usize) -> String {
+        let mut out = vec![];
+        write_rows(args, threads, &mut out).unwrap();
+        String::from_utf8(out).unwrap()
+    }
+
+    #[test]
+    fn rows_follow_the_rules() {
+        for (names, stations) in [
+            (NameKind::Official, 413),
+            (NameKind::Random, 10_000),
+            (NameKind::Long, 50),
+            (NameKind::Unicode, 10_000),
+        ] {
+            for extreme in [false, true] {
+                let args = args(20_000, names, stations, extreme);
+                let data = rows(&args, 3);
+                let mut seen = HashSet::new();
+                for line in data.split_terminator('\n') {
+                    let (name, t) = line.split_once(';').unwrap();
+                    assert!((1..=100).contains(&name.len()), "{name}");
+                    if names == NameKind::Long {
+                        assert_eq!(name.len(), 100, "{name}");
+                    }
+                    let (int, dec) = t.strip_prefix('-').unwrap_or(t).split_once('.').unwrap();
+                    assert!((1..=2).contains(&int.len()) && dec.len() == 1, "{t}");
+                    assert!(!int.starts_with('0') || int == "0", "{t}");
+                    seen.insert(name);
+                }
+                assert_eq!(data.lines().count(), 20_000);
+                assert!(seen.len() <= stations);
+                assert!(seen.len() > stations.min(20_000) / 2);
+                if extreme {
+                    assert!(data.contains(";-99.9\n") && data.contains(";99.9\n"));
+                }
+                // and the reference aggregates it
+                reference::aggregate(&data);
+            }
+        }
+    }
+
+    #[test]
+    fn same_rows_on_any_thread_count() {
+        let args = args(3 * BLOCK_ROWS + 5, NameKind::Official, 413, false);
+        let one = rows(&args, 1);
+        assert_eq!(one.lines().count() as u64, args.rows);
+        assert_eq!(rows(&args, 4), one);
+        let other_seed = GenerateArgs { seed: 8, ..args };
+        assert_ne!(rows(&other_seed, 4), one);
+    }
+}
@@ -0,0 +1,349 @@
+//! Measures how well a hasher spreads station names over the `StackMap`
+//! buckets: collisions, probe lengths and avalanche on the masked bits. The
+//! same numbers describe the `StackMap`s of a real run, see [`HashStat`].
+
+#[cfg(test)]
+use std::hash::BuildHasher;
+use std::{
+    collections::{HashMap, HashSet},
+    fmt::Display,
+};
+
+use crate::hashmap::{StackMap, home_bucket};
+
+/// Number of bits of the hash used to pick a bucket
+#[cfg(test)]
+pub fn bucket_bits() -> u32 {
+    StackMap::<(), (), ()>::buckets().trailing_zeros()
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct Quality {
+    pub keys: usize,
+    /// keys sharing their full 64 bits hash with an earlier key
+    pub full_collisions: usize,
+    /// keys whose home bucket is already the home of an earlier key
+    pub bucket_collisions: usize,
+    /// same, for a perfectly uniform hash
+    pub expected_bucket_collisions: f64,
+    pub max_bucket_load: usize,
+    /// probe lengths once the keys are in a `StackMap`
+    pub max_probe: usize,
+    pub mean_probe: f64,
+}
+
+impl Display for Quality {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let Self {
+            keys,
+            full_collisions,
+            bucket_collisions,
+            expected_bucket_collisions,
+            max_bucket_load,
+            max_probe,
+            mean_probe,
+        } = self;
+        write!(
+            f,
+            "keys={keys} full_collisions={full_collisions} \
+             bucket_collisions={bucket_collisions} \
+             expected_bucket_collisions={expected_bucket_collisions:.1} \
+             max_bucket_load={max_bucket_load} max_probe={max_probe} mean_probe={mean_probe:.3}"
+        )
+    }
+}
+
+/// Expected number of keys landing on an already used bucket, when `keys`
+/// keys are thrown uniformly in `buckets` buckets
+pub fn expected_collisions(keys: usize, buckets: usize) -> f64 {
+    let (n, m) = (keys as f64, buckets as f64);
+    n - m * (1. - (1. - 1. / m).powf(n))
+}
+
+/// Occupancy of every bucket when the keys are placed at their home
+pub fn bucket_loads(hashes: impl IntoIterator<Item = u64>) -> Vec<usize> {
+    let mut loads = vec![0; StackMap::<(), (), ()>::buckets()];
+    for h in hashes {
+        loads[home_bucket(h)] += 1;
+    }
+    loads
+}
+
+#[cfg(test)]
+pub fn measure<H: BuildHasher>(hasher: H, keys: &[impl AsRef<[u8]>]) -> Quality {
+    measure_keys(hasher, keys.iter().map(|k| k.as_ref())).quality
+}
+
+/// [`measure_map`] of a `StackMap` holding `keys`
+#[cfg(test)]
+pub fn measure_keys<'a, H: BuildHasher>(
+    hasher: H,
+    keys: impl IntoIterator<Item = &'a [u8]>,
+) -> MapStats {
+    let mut map = StackMap::<&[u8], (), H>::new_boxed_with_hasher(hasher);
+    for k in keys {
+        map.insert(k, ());
+    }
+    measure_map(&map)
+}
+
+/// Numbers about a filled map, from the hashes it stored
+pub fn measure_map<K, V, H>(map: &StackMap<K, V, H>) -> MapStats {
+    let hashes: Vec<_> = map.iter_hashed().map(|(h, _, _)| h).collect();
+    let keys = hashes.len();
+
+    let full_collisions = keys - hashes.iter().collect::<HashSet<_>>().len();
+
+    let loads = bucket_loads(hashes.iter().copied());
+    let used = loads.iter().filter(|&&l| l > 0).count();
+    let max_bucket_load = loads.iter().copied().max().unwrap_or(0);
+    let mut load_histogram = vec![0; max_bucket_load + 1];
+    for l in &loads {
+        load_histogram[*l] += 1;
+    }
+
+    let (max_probe, total_probe) = map
+        .probe_lengths()
+        .fold((0, 0), |(max, total), p| (p.max(max), total + p));
+
+    MapStats {
+        buckets: loads.len(),
+        quality: Quality {
+            keys,
+            full_collisions,
+            bucket_collisions: keys - used,
+            expected_bucket_collisions: expected_collisions(keys, loads.len()),
+            max_bucket_load,
+            max_probe,
+            mean_probe: total_probe as f64 / keys.max(1) as f64,
+        },
+        load_histogram,
+    }
+}
+
+/// [`Quality`] of the keys of an actual map, with its occupancy
+#[derive(Debug, Clone)]
+pub struct MapStats {
+    pub buckets: usize,
+    pub quality: Quality,
+    /// number of buckets that are the home of 0, 1, 2... keys
+    pub load_histogram: Vec<usize>,
+}
+
+impl MapStats {
+    pub fn load_factor(&self) -> f64 {
+        self.quality.keys as f64 / self.buckets as f64
+    }
+}
+
+impl Display for MapStats {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let histogram = self
+            .load_histogram
+            .iter()
+            .map(|n| n.to_string())
+            .collect::<Vec<_>>()
+            .join(",");
+        write!(
+            f,
+            "buckets={} load_factor={:.4} {} load_histogram={histogram}",
+            self.buckets,
+            self.load_factor(),
+            self.quality
+        )
+    }
+}
+
+/// Maps that can tell how their keys are laid out, for `--stats`
+pub trait HashStat {
+    type Stats: Display;
+
+    fn hash_stats(&self) -> Self::Stats;
+}
+
+impl<K, V, H> HashStat for StackMap<K, V, H> {
+    type Stats = MapStats;
+
+    fn hash_stats(&self) -> MapStats {
+        measure_map(self)
+    }
+}
+
+/// What the maps that grow on the heap know of themselves: they keep no
+/// probe lengths, so there is no [`Quality`] to report
+#[derive(Debug, Clone, Copy)]
+pub struct CapacityStats {
+    pub keys: usize,
+    /// keys the map holds before it grows
+    pub capacity: usize,
+}
+
+impl Display for CapacityStats {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "keys={} capacity={} load_factor={:.4}",
+            self.keys,
+            self.capacity,
+            self.keys as f64 / self.capacity as f64
+        )
+    }
+}
+
+impl<K, V, H> HashStat for HashMap<K, V, H> {
+    type Stats = CapacityStats;
+
+    fn hash_stats(&self) -> CapacityStats {
+        CapacityStats {
+            keys: self.len(),
+            capacity: self.capacity(),
+        }
+    }
+}
+
+/// Worst avalanche bias on the bits picking the bucket: flipping a single
+/// bit of the first 16 bytes of a key should flip each of those output bits
+/// half of the time. Returns the largest `|P(flip) - 0.5|` over all the
+/// (input bit, output bit) pairs, `0` is perfect and `0.5` means some output
+/// bit ignores (or copies) some input bit. Input bits that too few keys are
+/// long enough to have are skipped, their estimate would be noise.
+#[cfg(test)]
+pub fn avalanche_bias<H: BuildHasher>(hasher: &H, keys: &[impl AsRef<[u8]>]) -> f64 {
+    static INPUT_BITS: usize = 16 * 8;
+    static MIN_TRIALS: usize = 100;
+    let out_bits = bucket_bits() as usize;
+    let mut flips = vec![0usize; INPUT_BITS * out_bits];
+    let mut trials = vec![0usize; INPUT_BITS];
+
+    let mut key = Vec::new();
+    for k in keys {
+        let k = k.as_ref();
+        let h = hasher.hash_one(k);
+        key.clear();
+        key.extend_from_slice(k);
+        for bit in 0..(k.len() * 8).min(INPUT_BITS) {
+            key[bit / 8] ^= 1 << (bit % 8);
+            let diff = h ^ hasher.hash_one(key.as_slice());
+            key[bit / 8] ^= 1 << (bit % 8);
+
+            trials[bit] += 1;
+            for out in 0..out_bits {
+                flips[bit * out_bits + out] += ((diff >> out) & 1) as usize;
+            }
+        }
+    }
+
+    flips
+        .chunks(out_bits)
+        .zip(trials)
+        .filter(|(_, t)| *t >= MIN_TRIALS)
+        .flat_map(|(f, t)| f.iter().map(move |&f| (f as f64 / t as f64 - 0.5).abs()))
+        .fold(0., f64::max)
+}
+
+#[cfg(test)]
+mod test {
+    use std::hash::{BuildHasher, BuildHasherDefault, DefaultHasher};
+
+    use rustc_hash::FxBuildHasher;
+
+    use super::{HashStat, avalanche_bias, measure};
+    use crate::{
+        hasher::{
+            FasHaserBuilder, FasHaserBuilderSimd, MHasher, SeededHasherBuilder,
+            StationHasherBuilder,
+        },
+        hashmap::StackMap,
+        rng::{SplitMix64, random_names},
+        stations::STATIONS,
+    };
+
+    fn stations() -> Vec<&'static [u8]> {
+        STATIONS.iter().map(|(s, _)| s.as_bytes()).collect()
+    }
+
+    fn random(n: usize) -> Vec<Vec<u8>> {
+        random_names(&mut SplitMix64::new(0x1b2c), n)
+    }
+
+    /// Bounds any hasher used by the map should meet. The slack is for the
+    /// sampling noise of a single key set.
+    fn assert_good_quality<H: BuildHasher + Clone>(hasher: H) {
+        for keys in [
+            stations().iter().map(|s| s.to_vec()).collect(),
+            random(10_000),
+        ] {
+            let q = measure(hasher.clone(), &keys);
+            assert_eq!(q.full_collisions, 0, "{q}");
+            assert!(
+                (q.bucket_collisions as f64) < 1.1 * q.expected_bucket_collisions + 10.,
+                "{q}"
+            );
+            assert!(q.mean_probe < 1.5, "{q}");
+        }
+        // 2000 keys put the sampling noise around 0.05
+        let bias = avalanche_bias(&hasher, &random(2000));
+        assert!(bias < 0.1, "avalanche_bias={bias}");
+    }
+
+    #[test]
+    fn station_quality() {
+        assert_good_quality(StationHasherBuilder);
+    }
+
+    #[test]
+    fn fx_quality() {
+        assert_good_quality(FxBuildHasher);
+    }
+
+    #[test]
+    fn seeded_quality() {
+        assert_good_quality(SeededHasherBuilder::default());
+    }
+
+    #[test]
+    fn map_stats() {
+        let mut map = StackMap::<&[u8], (), _>::new_boxed_with_hasher(MHasher::default());
+        for s in stations() {
+            map.insert(s, ());
+        }
+        let stats = map.hash_stats();
+        assert_eq!(stats.quality.keys, STATIONS.len());
+        assert_eq!(stats.load_histogram.iter().sum::<usize>(), stats.buckets);
+        assert_eq!(
+            (0..)
+                .zip(&stats.load_histogram)
+                .map(|(l, n)| l * n)
+                .sum::<usize>(),
+            STATIONS.len()
+        );
+        let line = stats.to_string();
+        assert!(
+            line.starts_with("buckets=16384 load_factor=0.0252 keys=413 "),
+            "{line}"
+        );
+    }
+
+    /// Prints the numbers for every hasher, to compare them:
+    /// `cargo test --release hash_quality_report -- --ignored --nocapture`
+    #[test]
+    #[ignore]
+    fn hash_quality_report() {
+        fn report<H: BuildHasher + Clone>(name: &str, hasher: H) {
+            for (set, keys) in [
+                ("stations", stations().iter().map(|s| s.to_vec()).collect()),
+                ("random_10k", random(10_000)),
+            ] {
+                let q = measure(hasher.clone(), &keys);
+                let bias = avalanche_bias(&hasher, &keys[..keys.len().min(2000)]);
+                println!("hasher={name} set={set} {q} avalanche_bias={bias:.3}");
+            }
+        }
+        report("fx", FxBuildHasher);
+        report("station", StationHasherBuilder);
+        report("seeded", SeededHasherBuilder::default());
+        report("fas", FasHaserBuilder);
+        report("fas_simd", FasHaserBuilderSimd);
+        report("sip", BuildHasherDefault::<DefaultHasher>::default());
+    }
+}
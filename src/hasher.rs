@@ -1,9 +1,11 @@
 use std::{
+    collections::hash_map::RandomState,
     hash::{BuildHasher, Hasher},
     simd::u8x4,
+    sync::OnceLock,
 };
 
-#[derive(Default)]
+#[derive(Default, Clone)]
 pub struct FasHaserBuilder;
 pub struct FashHaser(uhash);
 
@@ -43,13 +45,12 @@ impl Hasher for FashHaser {
                 let hi = bytes[len - 1] as u32;
                 self.0 ^= lo ^ (mid << 8) ^ (hi << 16)
             }
-            self.0.wrapping_add(3851351);
         } else {
             self.long_hash(bytes);
         }
     }
 
-    fn write_length_prefix(&mut self, len: usize) {}
+    fn write_length_prefix(&mut self, _len: usize) {}
 
     fn write_u32(&mut self, i: u32) {
         self.0 ^= self.0.rotate_left(HALF_SIZE) ^ i
@@ -107,7 +108,7 @@ pub fn to_usize_padded(slice: &[u8]) -> uhash {
     uhash::from_ne_bytes(buf)
 }
 
-#[derive(Default)]
+#[derive(Default, Clone)]
 pub struct FasHaserBuilderSimd;
 pub struct FashHaserSimd(u8xh);
 type u8xh = u8x4;
@@ -138,7 +139,7 @@ impl Hasher for FashHaserSimd {
         self.0 ^= u8xh::load_or_default(remained);
     }
 
-    fn write_length_prefix(&mut self, len: usize) {}
+    fn write_length_prefix(&mut self, _len: usize) {}
 
     fn write_usize(&mut self, i: usize) {
         let [x, y] = split_u64_unsafe(i as u64);
@@ -154,7 +155,258 @@ fn split_u64_unsafe(value: u64) -> [u8x4; 2] {
     }
 }
 
-/// I give up, my hashes have too many colisions
+/// Hasher for station names: reads the first and the last 8 bytes and the
+/// length, and mixes them with a folded multiply. The bytes in between are
+/// only read for names longer than 16 bytes, which are rare.
+///
+/// Unfinished: it matches Fx on the quality suite, but it isn't faster end to
+/// end (`cargo bench --bench run`), so it isn't the default.
+#[derive(Default, Clone, Copy)]
+pub struct StationHasherBuilder;
+pub struct StationHasher(u64);
+
+impl BuildHasher for StationHasherBuilder {
+    type Hasher = StationHasher;
+
+    fn build_hasher(&self) -> Self::Hasher {
+        StationHasher(0)
+    }
+}
+
+impl Hasher for StationHasher {
+    fn finish(&self) -> u64 {
+        self.0
+    }
+
+    fn write(&mut self, bytes: &[u8]) {
+        self.0 = hash_station(bytes, self.0)
+    }
+
+    // the length is already part of `hash_station`
+    fn write_length_prefix(&mut self, _len: usize) {}
+}
+
+// digits of pi, as in foldhash
+static STATION_K: [u64; 3] = [
+    0x243f_6a88_85a3_08d3,
+    0x1319_8a2e_0370_7344,
+    0xa409_3822_299f_31d0,
+];
+
+/// Multiplies on 128 bits and xors the halves. Every input bit can reach
+/// every output bit, the low ones included, which is what `MASK` keeps.
+#[inline]
+fn folded_multiply(x: u64, y: u64) -> u64 {
+    let full = (x as u128) * (y as u128);
+    (full as u64) ^ ((full >> 64) as u64)
+}
+
+/// The `min(8, len)` first bytes, zero padded, as a little endian word
+#[inline]
+pub fn first_word(bytes: &[u8]) -> u64 {
+    let len = bytes.len();
+    if len >= 8 {
+        u64::from_le_bytes(bytes[..8].try_into().unwrap())
+    } else if len >= 4 {
+        // two overlapping loads instead of a variable length copy
+        let lo = u32::from_le_bytes(bytes[..4].try_into().unwrap()) as u64;
+        let hi = u32::from_le_bytes(bytes[len - 4..].try_into().unwrap()) as u64;
+        lo | (hi << (8 * (len - 4)))
+    } else if len > 0 {
+        let lo = bytes[0] as u64;
+        let mid = (bytes[len / 2] as u64) << (8 * (len / 2));
+        let hi = (bytes[len - 1] as u64) << (8 * (len - 1));
+        lo | mid | hi
+    } else {
+        0
+    }
+}
+
+/// The `min(8, len)` last bytes, zero padded, as a little endian word
+#[inline]
+pub fn last_word(bytes: &[u8]) -> u64 {
+    first_word(&bytes[bytes.len().saturating_sub(8)..])
+}
+
+/// Hash of a station name from its first and last words (see [`first_word`]
+/// and [`last_word`]) and its length. `middle` are the bytes strictly between
+/// the first and the last 8, only non empty for names longer than 16 bytes.
+#[inline]
+pub fn hash_station_parts(first: u64, last: u64, middle: &[u8], len: usize, seed: u64) -> u64 {
+    // the length goes in the top byte of the last word. Names under 8 bytes
+    // leave that byte zero, so their zero padding can't hide a length
+    // difference; in longer names it is xored with a name byte
+    let last = last ^ ((len as u64) << 56);
+    let mut h = folded_multiply(first ^ seed ^ STATION_K[1], last ^ STATION_K[2]);
+    for chunk in middle.chunks(8) {
+        h = folded_multiply(h ^ first_word(chunk), STATION_K[1]);
+    }
+    // the low bits of a single product only see the low bits of its inputs
+    folded_multiply(h, STATION_K[0])
+}
+
+#[inline]
+pub fn hash_station(bytes: &[u8], seed: u64) -> u64 {
+    let len = bytes.len();
+    if len >= 8 {
+        let first = u64::from_le_bytes(bytes[..8].try_into().unwrap());
+        let last = u64::from_le_bytes(bytes[len - 8..].try_into().unwrap());
+        let middle = if len > 16 { &bytes[8..len - 8] } else { &[] };
+        hash_station_parts(first, last, middle, len, seed)
+    } else {
+        // the first and last words are the same
+        let word = first_word(bytes);
+        hash_station_parts(word, word, &[], len, seed)
+    }
+}
+
+/// Hashers the parser can feed with the bytes it already loaded while it
+/// looked for the `;`
+pub trait ScanHasher: BuildHasher + Sized {
+    /// Same as `self.hash_one(station)`. `head` holds the first
+    /// `min(16, station.len())` bytes of `station` in its low bytes, little
+    /// endian, and anything above.
+    #[inline]
+    fn hash_scanned(&self, station: &[u8], _head: u128) -> u64 {
+        self.hash_one(station)
+    }
+}
+
+impl ScanHasher for rustc_hash::FxBuildHasher {}
+
+impl ScanHasher for StationHasherBuilder {
+    /// The words of names up to 16 bytes are all in `head`, the station
+    /// bytes are only read back for longer ones
+    #[inline]
+    fn hash_scanned(&self, station: &[u8], head: u128) -> u64 {
+        let len = station.len();
+        if len > 16 {
+            return hash_station(station, 0);
+        }
+        let (first, last) = if len >= 8 {
+            (head as u64, (head >> (8 * (len - 8))) as u64)
+        } else {
+            // zero padded, see `first_word`
+            let word = head as u64 & ((1 << (8 * len)) - 1);
+            (word, word)
+        };
+        hash_station_parts(first, last, &[], len, 0)
+    }
+}
+
+/// `ahash` keyed by a seed drawn once per process, for untrusted input: the
+/// layout of the maps can't be predicted, so names can't be picked to pile
+/// up in a few buckets. All the default instances share the seed, so the
+/// maps of the threads keep the same layout and merge by bucket ranges.
+#[derive(Clone)]
+pub struct SeededHasherBuilder {
+    seed: u64,
+    state: ahash::RandomState,
+}
+
+impl SeededHasherBuilder {
+    pub fn with_seed(seed: u64) -> Self {
+        Self {
+            seed,
+            state: ahash::RandomState::with_seeds(
+                seed,
+                seed ^ STATION_K[0],
+                seed ^ STATION_K[1],
+                seed ^ STATION_K[2],
+            ),
+        }
+    }
+}
+
+impl Default for SeededHasherBuilder {
+    fn default() -> Self {
+        static SEED: OnceLock<u64> = OnceLock::new();
+        Self::with_seed(*SEED.get_or_init(|| RandomState::new().hash_one(0)))
+    }
+}
+
+impl BuildHasher for SeededHasherBuilder {
+    type Hasher = ahash::AHasher;
+
+    fn build_hasher(&self) -> Self::Hasher {
+        self.state.build_hasher()
+    }
+}
+
+impl ScanHasher for SeededHasherBuilder {}
+
+/// Hashers that can start over with another seed, when the probe guard finds
+/// that the current one clusters the keys
+pub trait Reseed: Sized {
+    /// A hasher with a fresh seed, `None` if there is no seed to change
+    fn reseed(&self) -> Option<Self> {
+        None
+    }
+}
+
+impl Reseed for rustc_hash::FxBuildHasher {}
+
+impl Reseed for StationHasherBuilder {}
+
+impl Reseed for SeededHasherBuilder {
+    fn reseed(&self) -> Option<Self> {
+        Some(Self::with_seed(RandomState::new().hash_one(self.seed)))
+    }
+}
+
+/// Everything `main` needs from the hasher of the maps
+pub trait MapHasher: ScanHasher + Reseed + Default + Clone + Send + Sync {}
+
+impl<H> MapHasher for H where H: ScanHasher + Reseed + Default + Clone + Send + Sync {}
+
+/// The `Fas` hashers have too many colisions, and the station one doesn't beat
+/// Fx yet, see [`StationHasherBuilder`]
 pub type MHasher = rustc_hash::FxBuildHasher;
+// pub type MHasher = StationHasherBuilder;
 // pub type MHasher = FasHaserBuilder;
 // pub type MHasher = FasHaserBuilderSimd;
+
+#[cfg(test)]
+mod test {
+    use proptest::{collection::vec, prelude::*};
+
+    use std::hash::BuildHasher;
+
+    use super::{Reseed, SeededHasherBuilder, first_word, hash_station, last_word};
+
+    /// zero padded copy, what the word loads are about
+    fn padded(bytes: &[u8]) -> u64 {
+        let mut buf = [0; 8];
+        buf[..bytes.len()].copy_from_slice(bytes);
+        u64::from_le_bytes(buf)
+    }
+
+    proptest! {
+        #[test]
+        fn words_are_zero_padded(bytes in vec(any::<u8>(), 0..40)) {
+            let n = bytes.len().min(8);
+            prop_assert_eq!(first_word(&bytes), padded(&bytes[..n]));
+            prop_assert_eq!(last_word(&bytes), padded(&bytes[bytes.len() - n..]));
+        }
+
+        /// trailing zeros must change the hash, the padding would hide them
+        #[test]
+        fn length_is_hashed(bytes in vec(any::<u8>(), 0..40), seed in any::<u64>()) {
+            let mut longer = bytes.clone();
+            longer.push(0);
+            prop_assert_ne!(hash_station(&bytes, seed), hash_station(&longer, seed));
+        }
+    }
+
+    #[test]
+    fn seeded_layouts() {
+        let name = b"Las Palmas de Gran Canaria".as_slice();
+        let (a, b) = (
+            SeededHasherBuilder::default(),
+            SeededHasherBuilder::default(),
+        );
+        assert_eq!(a.hash_one(name), b.hash_one(name));
+        let c = a.reseed().unwrap();
+        assert_ne!(a.hash_one(name), c.hash_one(name));
+    }
+}
@@ -1,19 +1,35 @@
 use std::borrow::Borrow;
-use std::cmp::Ordering;
 use std::hash::BuildHasher;
 use std::hash::Hash;
-
-use smallvec::SmallVec;
-
-use crate::HashStat;
+use std::ops::Range;
+use std::simd::{cmp::SimdPartialEq, u8x16};
 
 static MAP_SIZE: usize = 1 << (10_000usize.highest_one().unwrap() + 1);
 static MASK: usize = MAP_SIZE - 1;
 // static MAP_SIZE: usize = 10_000;
 
-static BUCKET_SIZE: usize = 1;
+/// Longest walk an insertion may take before the map counts as clustered.
+/// Good hashes stay around 40 with 10k keys.
+pub const PROBE_LIMIT: usize = 128;
+
+/// Bucket where a key with hash `hashed` is looked up first
+pub const fn home_bucket(hashed: u64) -> usize {
+    // hashed as usize % MAP_SIZE
+    hashed as usize & MASK
+}
+
+/// Control bytes scanned at once
+type Group = u8x16;
+const GROUP: usize = Group::LEN;
 
-// struct Bucket<T>(SmallVec<[T; BUCKET_SIZE]>);
+/// Control byte of an empty bucket. Full ones hold a 7 bits tag of the hash,
+/// so the high bit tells them apart.
+const EMPTY: u8 = 0x80;
+
+/// The tag comes from the high bits, the home bucket from the low ones
+const fn tag(hashed: u64) -> u8 {
+    (hashed >> 57) as u8
+}
 
 struct ContentBucket<K, V> {
     hash_mem: u64,
@@ -23,10 +39,17 @@ struct ContentBucket<K, V> {
 
 struct Bucket<K, V>(Option<ContentBucket<K, V>>);
 
+/// Linear probing table, with a control byte per bucket so that probes look
+/// at `GROUP` buckets at once and only compare the keys whose tag matches.
 pub struct StackMap<K, V, H> {
     content: [Bucket<K, V>; MAP_SIZE],
+    /// one per bucket, then the first `GROUP` ones again, so that a group can
+    /// be loaded from any bucket without wrapping
+    ctrl: [u8; MAP_SIZE + GROUP],
     hasher: H,
     size: usize,
+    /// longest walk from a home bucket to a free one seen while inserting
+    longest_probe: usize,
 }
 
 impl<K, V> Default for Bucket<K, V> {
@@ -35,12 +58,25 @@ impl<K, V> Default for Bucket<K, V> {
     }
 }
 
+impl<K, V, H: Default> Default for StackMap<K, V, H> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
 impl<K, V, H> StackMap<K, V, H> {
+    /// Number of buckets, all the maps share it
+    pub const fn buckets() -> usize {
+        MAP_SIZE
+    }
+
     pub fn new_with_hasher(hasher: H) -> Self {
         Self {
             content: ::std::array::from_fn(|_| Default::default()),
+            ctrl: [EMPTY; _],
             hasher,
             size: 0,
+            longest_probe: 0,
         }
     }
 
@@ -51,6 +87,32 @@ impl<K, V, H> StackMap<K, V, H> {
         Self::new_with_hasher(Default::default())
     }
 
+    /// Builds the map directly on the heap, for when it can't go through a
+    /// (small) stack first
+    pub fn new_boxed_with_hasher(hasher: H) -> Box<Self> {
+        let mut map = Box::<Self>::new_uninit();
+        let ptr = map.as_mut_ptr();
+        // safety: every field is written before `assume_init`
+        unsafe {
+            let content = (&raw mut (*ptr).content).cast::<Bucket<K, V>>();
+            for i in 0..MAP_SIZE {
+                content.add(i).write(Default::default());
+            }
+            (&raw mut (*ptr).ctrl).write([EMPTY; _]);
+            (&raw mut (*ptr).hasher).write(hasher);
+            (&raw mut (*ptr).size).write(0);
+            (&raw mut (*ptr).longest_probe).write(0);
+            map.assume_init()
+        }
+    }
+
+    pub fn new_boxed() -> Box<Self>
+    where
+        H: Default,
+    {
+        Self::new_boxed_with_hasher(Default::default())
+    }
+
     pub fn iter(&self) -> impl Iterator<Item = (&K, &V)> {
         self.content.iter().filter_map(|Bucket(b)| {
             let ContentBucket { key, value, .. } = b.as_ref()?;
@@ -61,98 +123,216 @@ impl<K, V, H> StackMap<K, V, H> {
     pub fn keys(&self) -> impl Iterator<Item = &K> {
         self.iter().map(|(k, _)| k)
     }
-}
 
-impl<K, V> PartialEq for ContentBucket<K, V>
-where
-    K: PartialEq,
-{
-    fn eq(&self, other: &Self) -> bool {
-        self.hash_mem == other.hash_mem && self.key == other.key
+    /// Same as [`Self::iter`] but with the stored hashes
+    pub fn iter_hashed(&self) -> impl Iterator<Item = (u64, &K, &V)> {
+        self.content.iter().filter_map(|Bucket(b)| {
+            let ContentBucket {
+                hash_mem,
+                key,
+                value,
+            } = b.as_ref()?;
+            Some((*hash_mem, key, value))
+        })
     }
-}
 
-impl<K, V> Eq for ContentBucket<K, V> where K: Eq {}
+    /// For every entry, how far it sits from its home bucket
+    pub fn probe_lengths(&self) -> impl Iterator<Item = usize> {
+        self.content
+            .iter()
+            .enumerate()
+            .filter_map(|(idx, Bucket(b))| {
+                let home = home_bucket(b.as_ref()?.hash_mem);
+                Some(idx.wrapping_sub(home) & MASK)
+            })
+    }
 
-impl<K, V> PartialOrd for ContentBucket<K, V>
-where
-    K: PartialOrd,
-{
-    fn partial_cmp(&self, other: &Self) -> Option<std::cmp::Ordering> {
-        match self.hash_mem.partial_cmp(&other.hash_mem) {
-            Some(Ordering::Equal) => {}
-            ord => return ord,
-        }
-        match self.key.partial_cmp(&other.key) {
-            Some(Ordering::Equal) => {}
-            ord => return ord,
-        }
-        Some(Ordering::Equal)
+    /// Empties the map, yielding the entries with their stored hashes
+    pub fn drain_hashed(&mut self) -> impl Iterator<Item = (u64, K, V)> {
+        self.ctrl.fill(EMPTY);
+        self.size = 0;
+        self.longest_probe = 0;
+        self.content.iter_mut().filter_map(|Bucket(b)| {
+            let ContentBucket {
+                hash_mem,
+                key,
+                value,
+            } = b.take()?;
+            Some((hash_mem, key, value))
+        })
     }
-}
 
-impl<K, V> Ord for ContentBucket<K, V>
-where
-    K: Ord,
-{
-    fn cmp(&self, other: &Self) -> std::cmp::Ordering {
-        match self.hash_mem.cmp(&other.hash_mem) {
-            Ordering::Equal => {}
-            ord => return ord,
-        }
-        match self.key.cmp(&other.key) {
-            Ordering::Equal => {}
-            ord => return ord,
+    fn set_ctrl(&mut self, idx: usize, ctrl: u8) {
+        self.ctrl[idx] = ctrl;
+        if idx < GROUP {
+            self.ctrl[MAP_SIZE + idx] = ctrl;
         }
-        Ordering::Equal
+    }
+
+    /// The control bytes of the `GROUP` buckets from `idx`, wrapping around
+    #[inline]
+    fn group(&self, idx: usize) -> Group {
+        Group::from_slice(&self.ctrl[idx..])
     }
 }
 
 impl<K, V, H> StackMap<K, V, H>
 where
-    K: Hash + Ord,
+    K: Hash + Eq,
     H: BuildHasher,
 {
     const fn get_idx(hashed: u64) -> usize {
-        // hashed as usize % MAP_SIZE
-        hashed as usize & MASK
+        home_bucket(hashed)
+    }
+
+    /// Whether both maps hash the same way, and thus put the same key in the
+    /// same home bucket. Hashers can't be compared, so this fingerprints
+    /// them on a couple of fixed values.
+    pub fn same_layout(&self, other: &Self) -> bool {
+        static PROBES: [u64; 2] = [0, 0x9e37_79b9_7f4a_7c15];
+        PROBES
+            .iter()
+            .all(|p| self.hasher.hash_one(p) == other.hasher.hash_one(p))
+    }
+
+    /// Iterates over the entries whose home bucket is in `homes`. Those sit
+    /// either in `homes` or in the run overflowing past its end, so only that
+    /// part of the table is scanned.
+    pub fn iter_homes(&self, homes: Range<usize>) -> impl Iterator<Item = (u64, &K, &V)> {
+        let overflow = (0..MAP_SIZE)
+            .map(|i| (homes.end + i) % MAP_SIZE)
+            .take_while(|&i| self.content[i].0.is_some())
+            .count();
+        (homes.start..homes.end + overflow)
+            .map(|i| i % MAP_SIZE)
+            .filter_map(move |i| {
+                let ContentBucket {
+                    hash_mem,
+                    key,
+                    value,
+                } = self.content[i].0.as_ref()?;
+                homes
+                    .contains(&Self::get_idx(*hash_mem))
+                    .then_some((*hash_mem, key, value))
+            })
     }
 
-    pub fn insert(&mut self, key: K, value: V) -> &mut V {
-        assert!(self.size < MAP_SIZE, "no space left");
+    pub fn hasher(&self) -> &H {
+        &self.hasher
+    }
 
+    pub fn len(&self) -> usize {
+        self.size
+    }
+
+    /// The probe guard: whether some insertion walked more than
+    /// [`PROBE_LIMIT`] buckets, meaning the hasher clusters these keys and
+    /// every lookup pays for it. Only [`Self::rehash`] resets it.
+    pub fn is_clustered(&self) -> bool {
+        self.longest_probe > PROBE_LIMIT
+    }
+
+    /// Moves every entry to where `hasher` puts it
+    pub fn rehash(&mut self, hasher: H) {
+        let entries: Vec<_> = self.drain_hashed().map(|(_, k, v)| (k, v)).collect();
+        self.hasher = hasher;
+        for (key, value) in entries {
+            let hashed = self.hasher.hash_one(&key);
+            self.insert_hashed(hashed, key, value);
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.size == 0
+    }
+
+    /// Inserts `key`, returning the value it replaced if it was already there.
+    pub fn insert(&mut self, key: K, value: V) -> Option<V> {
         let hashed = self.hasher.hash_one(&key);
+        self.insert_prehashed(hashed, key, value)
+    }
 
-        let mut new_bucket = ContentBucket {
+    /// Same as [`Self::insert`], with `hashed` the hash of `key` under the
+    /// map's hasher
+    pub fn insert_prehashed(&mut self, hashed: u64, key: K, value: V) -> Option<V> {
+        debug_assert_eq!(hashed, self.hasher.hash_one(&key), "wrong hash");
+        match self.raw_entry_mut().from_key_hashed_nocheck(hashed, &key) {
+            RawEntryMut::Occupied(mut entry) => Some(entry.insert(value)),
+            RawEntryMut::Vacant(entry) => {
+                entry.insert(key, value);
+                None
+            }
+        }
+    }
+
+    /// Inserts `key`, which must not be in the map yet, with the precomputed
+    /// `hashed`, in the first empty bucket from its home. Returns its index.
+    fn insert_hashed(&mut self, hashed: u64, key: K, value: V) -> usize {
+        // keep at least one empty bucket so that probing always terminates
+        assert!(self.size + 1 < MAP_SIZE, "no space left");
+
+        let home = Self::get_idx(hashed);
+        let mut pos = home;
+        // this will not loop because there is a least one free space
+        let idx = loop {
+            let empty = self.group(pos).simd_eq(Group::splat(EMPTY)).to_bitmask();
+            if empty != 0 {
+                break (pos + empty.trailing_zeros() as usize) & MASK;
+            }
+            pos = (pos + GROUP) & MASK
+        };
+
+        self.content[idx] = Bucket(Some(ContentBucket {
             hash_mem: hashed,
             key,
             value,
-        };
+        }));
+        self.set_ctrl(idx, tag(hashed));
+        self.size += 1;
+        self.longest_probe = self.longest_probe.max(idx.wrapping_sub(home) & MASK);
+        idx
+    }
 
-        let mut idx = Self::get_idx(hashed);
+    /// Finds the index of `key` by linear probing from its home bucket, a
+    /// group of buckets at a time. Only the buckets with the tag of `hashed`
+    /// before the first empty one are candidates.
+    #[inline]
+    fn find<Q>(&self, hashed: u64, key: &Q) -> Option<usize>
+    where
+        K: Borrow<Q>,
+        Q: ?Sized,
+        Q: Eq,
+    {
+        let mut pos = Self::get_idx(hashed);
+        // most keys sit at home, checking it first skips the control bytes
+        if let Bucket(Some(bucket)) = &self.content[pos]
+            && bucket.hash_mem == hashed
+            && bucket.key.borrow() == key
+        {
+            return Some(pos);
+        }
 
-        while let Bucket(Some(bucket)) = &mut self.content[idx] {
-            match new_bucket.cmp(bucket) {
-                Ordering::Equal => {
-                    break;
-                    // bucket.value = new_bucket.value;
-                    // return  &mut bucket.value;
-                    // std::mem::swap(&mut new_bucket.value, &mut bucket.value);
-                    // return Some(new_bucket.value);
+        let tag = Group::splat(tag(hashed));
+        loop {
+            let group = self.group(pos);
+            let empty = group.simd_eq(Group::splat(EMPTY)).to_bitmask();
+            // the run ends at the first empty bucket
+            let in_run = empty.wrapping_sub(1) & !empty;
+            let mut candidates = group.simd_eq(tag).to_bitmask() & in_run;
+            while candidates != 0 {
+                let idx = (pos + candidates.trailing_zeros() as usize) & MASK;
+                // safety: the control byte holds a tag, so the bucket is full
+                let bucket = unsafe { self.content[idx].0.as_ref().unwrap_unchecked() };
+                if bucket.hash_mem == hashed && bucket.key.borrow() == key {
+                    return Some(idx);
                 }
-                // keep the smallest the closest to its "true" location
-                Ordering::Less => std::mem::swap(&mut new_bucket, bucket),
-
-                Ordering::Greater => {}
+                candidates &= candidates - 1;
             }
-
-            // this will not loop because there is a least one free space
-            idx = (idx + 1) % MAP_SIZE
+            if empty != 0 {
+                return None;
+            }
+            pos = (pos + GROUP) & MASK
         }
-        self.content[idx] = Bucket(Some(new_bucket));
-        self.size += 1;
-
-        &mut unsafe { self.content[idx].0.as_mut().unwrap_unchecked() }.value
     }
 
     pub fn get_mut<'a, Q>(&'a mut self, key: &Q) -> Option<&'a mut V>
@@ -162,86 +342,636 @@ where
         Q: Hash + Eq,
     {
         let hashed = self.hasher.hash_one(key);
-
-        let mut idx = Self::get_idx(hashed);
-        let idx = loop {
-            match &self.content[idx] {
-                Bucket(None) => return None,
-                Bucket(Some(ContentBucket {
-                    hash_mem,
-                    key: ckey,
-                    ..
-                })) if *hash_mem == hashed && ckey.borrow() == key => break idx,
-                _ => idx = (idx + 1) % MAP_SIZE,
-            }
-        };
+        let idx = self.find(hashed, key)?;
         // safety we just computed `idx` above
         let ContentBucket { value, .. } =
             unsafe { self.content[idx].0.as_mut().unwrap_unchecked() };
         Some(value)
     }
 
+    /// Same as [`Self::get`], with `hashed` the hash of `key` under the
+    /// map's hasher
+    pub fn get_prehashed<'a, Q>(&'a self, hashed: u64, key: &Q) -> Option<&'a V>
+    where
+        K: Borrow<Q>,
+        Q: ?Sized,
+        Q: Hash + Eq,
+    {
+        debug_assert_eq!(hashed, self.hasher.hash_one(key), "wrong hash");
+        let idx = self.find(hashed, key)?;
+        // safety we just computed `idx` above
+        let ContentBucket { value, .. } =
+            unsafe { self.content[idx].0.as_ref().unwrap_unchecked() };
+        Some(value)
+    }
+
     pub fn get<'a, Q>(&'a self, key: &Q) -> Option<&'a V>
+    where
+        K: Borrow<Q>,
+        Q: ?Sized,
+        Q: Hash + Eq,
+    {
+        self.get_prehashed(self.hasher.hash_one(key), key)
+    }
+
+    pub fn remove<Q>(&mut self, key: &Q) -> Option<V>
+    where
+        K: Borrow<Q>,
+        Q: ?Sized,
+        Q: Hash + Eq,
+    {
+        self.remove_entry(key).map(|(_, v)| v)
+    }
+
+    pub fn remove_entry<Q>(&mut self, key: &Q) -> Option<(K, V)>
     where
         K: Borrow<Q>,
         Q: ?Sized,
         Q: Hash + Eq,
     {
         let hashed = self.hasher.hash_one(key);
+        let idx = self.find(hashed, key)?;
+        Some(self.remove_at(idx))
+    }
 
-        let mut idx = Self::get_idx(hashed);
-        loop {
-            match &self.content[idx] {
-                Bucket(None) => return None,
-                Bucket(Some(ContentBucket {
-                    hash_mem,
-                    key: ckey,
-                    value,
-                })) if *hash_mem == hashed && ckey.borrow() == key => return Some(value),
-                _ => idx = (idx + 1) % MAP_SIZE,
+    /// Empties the bucket at `idx` and closes the gap with a backward shift:
+    /// every following bucket of the run that is still reachable from its home
+    /// when moved into the hole is pulled back. This keeps the "no empty bucket
+    /// between home and position" invariant `find` relies on.
+    fn remove_at(&mut self, idx: usize) -> (K, V) {
+        // safety: only called with an index returned by `find`
+        let ContentBucket { key, value, .. } =
+            unsafe { self.content[idx].0.take().unwrap_unchecked() };
+        self.size -= 1;
+
+        let mut hole = idx;
+        let mut idx = (idx + 1) % MAP_SIZE;
+        while let Bucket(Some(bucket)) = &self.content[idx] {
+            let home = Self::get_idx(bucket.hash_mem);
+            // distances are taken modulo `MAP_SIZE` to handle the wrap around
+            let dist_to_home = idx.wrapping_sub(home) & MASK;
+            let dist_to_hole = idx.wrapping_sub(hole) & MASK;
+            if dist_to_hole <= dist_to_home {
+                self.set_ctrl(hole, self.ctrl[idx]);
+                self.content[hole] = Bucket(self.content[idx].0.take());
+                hole = idx;
             }
+            idx = (idx + 1) % MAP_SIZE
         }
+        self.set_ctrl(hole, EMPTY);
+
+        (key, value)
     }
+
+    /// Raw entry API, keyed by anything `K` borrows as. Lets the caller
+    /// provide the hash, and only asks for an owned key when inserting.
+    pub fn raw_entry_mut(&mut self) -> RawEntryBuilderMut<'_, K, V, H> {
+        RawEntryBuilderMut { map: self }
+    }
+
+    /// Entry API keyed by a borrowed key. The owned `K` is only built (with
+    /// `From<&Q>`) if the key has to be inserted, so hitting an existing key
+    /// neither allocates nor hashes twice.
+    pub fn entry_ref<'a, 'b, Q>(&'a mut self, key: &'b Q) -> EntryRef<'a, 'b, K, Q, V, H>
+    where
+        K: Borrow<Q>,
+        Q: ?Sized,
+        Q: Hash + Eq,
+    {
+        let hashed = self.hasher.hash_one(key);
+        self.entry_ref_prehashed(hashed, key)
+    }
+
+    /// Same as [`Self::entry_ref`], with `hashed` the hash of `key` under the
+    /// map's hasher, as computed by the parser
+    pub fn entry_ref_prehashed<'a, 'b, Q>(
+        &'a mut self,
+        hashed: u64,
+        key: &'b Q,
+    ) -> EntryRef<'a, 'b, K, Q, V, H>
+    where
+        K: Borrow<Q>,
+        Q: ?Sized,
+        Q: Hash + Eq,
+    {
+        debug_assert_eq!(hashed, self.hasher.hash_one(key), "wrong hash");
+        match self.find(hashed, key) {
+            Some(idx) => EntryRef::Occupied(OccupiedEntry { map: self, idx }),
+            None => EntryRef::Vacant(VacantEntryRef {
+                map: self,
+                hashed,
+                key,
+            }),
+        }
+    }
+}
+
+pub struct RawEntryBuilderMut<'a, K, V, H> {
+    map: &'a mut StackMap<K, V, H>,
+}
+
+pub enum RawEntryMut<'a, K, V, H> {
+    Occupied(OccupiedEntry<'a, K, V, H>),
+    Vacant(RawVacantEntryMut<'a, K, V, H>),
+}
+
+pub struct OccupiedEntry<'a, K, V, H> {
+    map: &'a mut StackMap<K, V, H>,
+    idx: usize,
+}
+
+pub struct RawVacantEntryMut<'a, K, V, H> {
+    map: &'a mut StackMap<K, V, H>,
+    hashed: u64,
 }
 
-impl<K, V> ContentBucket<K, V> {
-    fn het_cmp<Q>(&self, hashed_other: u64, key: &Q) -> Ordering
+pub enum EntryRef<'a, 'b, K, Q: ?Sized, V, H> {
+    Occupied(OccupiedEntry<'a, K, V, H>),
+    Vacant(VacantEntryRef<'a, 'b, K, Q, V, H>),
+}
+
+pub struct VacantEntryRef<'a, 'b, K, Q: ?Sized, V, H> {
+    map: &'a mut StackMap<K, V, H>,
+    hashed: u64,
+    key: &'b Q,
+}
+
+// same names as hashbrown's raw entry API
+#[allow(clippy::wrong_self_convention)]
+impl<'a, K, V, H> RawEntryBuilderMut<'a, K, V, H>
+where
+    K: Hash + Eq,
+    H: BuildHasher,
+{
+    pub fn from_key<Q>(self, key: &Q) -> RawEntryMut<'a, K, V, H>
+    where
+        K: Borrow<Q>,
+        Q: ?Sized,
+        Q: Hash + Eq,
+    {
+        let hashed = self.map.hasher.hash_one(key);
+        self.from_key_hashed_nocheck(hashed, key)
+    }
+
+    /// Same as [`Self::from_key`] but trusts `hashed` to be the hash of `key`
+    /// under the map's hasher.
+    pub fn from_key_hashed_nocheck<Q>(self, hashed: u64, key: &Q) -> RawEntryMut<'a, K, V, H>
     where
         K: Borrow<Q>,
-        Q: Ord,
         Q: ?Sized,
+        Q: Eq,
     {
-        match self.hash_mem.cmp(&hashed_other) {
-            Ordering::Equal => {}
-            ord => return ord,
+        match self.map.find(hashed, key) {
+            Some(idx) => RawEntryMut::Occupied(OccupiedEntry { map: self.map, idx }),
+            None => RawEntryMut::Vacant(RawVacantEntryMut {
+                map: self.map,
+                hashed,
+            }),
         }
-        match self.key.borrow().cmp(key) {
-            Ordering::Equal => {}
-            ord => return ord,
+    }
+}
+
+impl<'a, K, V, H> RawEntryMut<'a, K, V, H>
+where
+    K: Hash + Eq,
+    H: BuildHasher,
+{
+    pub fn or_insert_with(self, f: impl FnOnce() -> (K, V)) -> (&'a mut K, &'a mut V) {
+        match self {
+            RawEntryMut::Occupied(entry) => entry.into_key_value(),
+            RawEntryMut::Vacant(entry) => {
+                let (key, value) = f();
+                entry.insert(key, value)
+            }
+        }
+    }
+}
+
+impl<'a, K, V, H> OccupiedEntry<'a, K, V, H>
+where
+    K: Hash + Eq,
+    H: BuildHasher,
+{
+    fn bucket(&self) -> &ContentBucket<K, V> {
+        // safety: occupied entries always point to a full bucket
+        unsafe { self.map.content[self.idx].0.as_ref().unwrap_unchecked() }
+    }
+
+    fn bucket_mut(&mut self) -> &mut ContentBucket<K, V> {
+        // safety: occupied entries always point to a full bucket
+        unsafe { self.map.content[self.idx].0.as_mut().unwrap_unchecked() }
+    }
+
+    pub fn key(&self) -> &K {
+        &self.bucket().key
+    }
+
+    pub fn get(&self) -> &V {
+        &self.bucket().value
+    }
+
+    pub fn get_mut(&mut self) -> &mut V {
+        &mut self.bucket_mut().value
+    }
+
+    pub fn insert(&mut self, value: V) -> V {
+        std::mem::replace(self.get_mut(), value)
+    }
+
+    pub fn into_mut(self) -> &'a mut V {
+        self.into_key_value().1
+    }
+
+    pub fn into_key_value(self) -> (&'a mut K, &'a mut V) {
+        // safety: occupied entries always point to a full bucket
+        let ContentBucket { key, value, .. } =
+            unsafe { self.map.content[self.idx].0.as_mut().unwrap_unchecked() };
+        (key, value)
+    }
+
+    pub fn remove_entry(self) -> (K, V) {
+        self.map.remove_at(self.idx)
+    }
+
+    pub fn remove(self) -> V {
+        self.remove_entry().1
+    }
+}
+
+impl<'a, K, V, H> RawVacantEntryMut<'a, K, V, H>
+where
+    K: Hash + Eq,
+    H: BuildHasher,
+{
+    /// Inserts `key` using the hash computed during the lookup, `key` must
+    /// therefore be the one that was looked up.
+    pub fn insert(self, key: K, value: V) -> (&'a mut K, &'a mut V) {
+        let idx = self.map.insert_hashed(self.hashed, key, value);
+        // safety: `insert_hashed` returns the index of the new bucket
+        let ContentBucket { key, value, .. } =
+            unsafe { self.map.content[idx].0.as_mut().unwrap_unchecked() };
+        (key, value)
+    }
+}
+
+impl<'a, 'b, K, Q, V, H> EntryRef<'a, 'b, K, Q, V, H>
+where
+    K: Hash + Eq + From<&'b Q>,
+    Q: ?Sized,
+    H: BuildHasher,
+{
+    pub fn or_insert_with(self, f: impl FnOnce() -> V) -> &'a mut V {
+        match self {
+            EntryRef::Occupied(entry) => entry.into_mut(),
+            EntryRef::Vacant(entry) => entry.insert(f()),
         }
-        Ordering::Equal
+    }
+
+    pub fn or_insert(self, value: V) -> &'a mut V {
+        self.or_insert_with(|| value)
+    }
+
+    pub fn or_default(self) -> &'a mut V
+    where
+        V: Default,
+    {
+        self.or_insert_with(Default::default)
     }
 }
 
-impl<K, V, H> HashStat for StackMap<K, V, H>
+impl<'a, 'b, K, Q, V, H> VacantEntryRef<'a, 'b, K, Q, V, H>
 where
-    K: Hash + Ord,
-    H: BuildHasher + Default,
+    K: Hash + Eq + From<&'b Q>,
+    Q: ?Sized,
+    H: BuildHasher,
 {
-    fn hash_stats(stats: &Self) {
-        println!();
-        let mut ret = std::collections::HashMap::new();
+    pub fn key(&self) -> &'b Q {
+        self.key
+    }
 
-        for k in stats.keys() {
-            let c: &mut usize = ret
-                .entry(Self::get_idx(H::default().hash_one(k)))
-                .or_default();
-            *c += 1usize;
+    pub fn insert(self, value: V) -> &'a mut V {
+        RawVacantEntryMut {
+            map: self.map,
+            hashed: self.hashed,
+        }
+        .insert(self.key.into(), value)
+        .1
+    }
+}
+
+#[cfg(test)]
+pub(crate) mod test {
+    use std::{
+        borrow::Borrow,
+        collections::HashMap,
+        hash::{BuildHasher, BuildHasherDefault, Hasher},
+    };
+
+    use proptest::{collection::vec, prelude::*, test_runner::TestRunner};
+    use rustc_hash::FxBuildHasher;
+
+    use super::{EMPTY, EntryRef, GROUP, MAP_SIZE, MASK, PROBE_LIMIT, RawEntryMut, StackMap, tag};
+
+    /// hashes a `u64` to itself, to control where keys land
+    #[derive(Default)]
+    struct Identity(u64);
+
+    impl Hasher for Identity {
+        fn finish(&self) -> u64 {
+            self.0
+        }
+
+        fn write(&mut self, _: &[u8]) {
+            unimplemented!()
         }
-        println!("size:{}", ret.len());
 
-        let max = *ret.values().max().unwrap();
-        let mean = ret.values().sum::<usize>() as f64 / (ret.len() as f64);
-        println!("max: {max}, mean: {mean}")
+        fn write_u64(&mut self, i: u64) {
+            self.0 = i
+        }
+    }
+
+    /// owned key that can be built from a borrowed `u64`
+    #[derive(PartialEq, Eq, PartialOrd, Ord, Hash, Debug)]
+    struct Key(u64);
+
+    impl From<&u64> for Key {
+        fn from(value: &u64) -> Self {
+            Self(*value)
+        }
+    }
+
+    impl Borrow<u64> for Key {
+        fn borrow(&self) -> &u64 {
+            &self.0
+        }
+    }
+
+    type IdMap = StackMap<Key, u64, BuildHasherDefault<Identity>>;
+
+    /// `Identity` mixed with a seed, `Seed(1)` keeps multiples of `MAP_SIZE`
+    /// in bucket 0
+    #[derive(Clone, Copy)]
+    struct Seed(u64);
+
+    impl BuildHasher for Seed {
+        type Hasher = SeedHasher;
+
+        fn build_hasher(&self) -> Self::Hasher {
+            SeedHasher(self.0, 0)
+        }
+    }
+
+    struct SeedHasher(u64, u64);
+
+    impl Hasher for SeedHasher {
+        fn finish(&self) -> u64 {
+            self.1.wrapping_mul(self.0).rotate_left(32)
+        }
+
+        fn write(&mut self, _: &[u8]) {
+            unimplemented!()
+        }
+
+        fn write_u64(&mut self, i: u64) {
+            self.1 = i
+        }
+    }
+
+    const LAST: u64 = MAP_SIZE as u64 - 1;
+
+    /// Runs `f` on a thread with a stack big enough for a few maps, for the
+    /// tests that keep them on the stack
+    pub(crate) fn on_big_stack(f: impl FnOnce() + Send + 'static) {
+        std::thread::Builder::new()
+            .stack_size(64 * 1024 * 1024)
+            .spawn(f)
+            .unwrap()
+            .join()
+            .unwrap_or_else(|e| std::panic::resume_unwind(e))
+    }
+
+    #[test]
+    fn entry_ref_returns_the_new_bucket() {
+        on_big_stack(|| {
+            let mut map = IdMap::new();
+            // same home, taken by the first key, so 3 lands in the next
+            // bucket and the entry must point there rather than at its home
+            map.insert(Key(3 + MAP_SIZE as u64), 1);
+            *map.entry_ref(&3).or_default() += 2;
+
+            assert_eq!(map.get(&3), Some(&2));
+            assert_eq!(map.get(&(3 + MAP_SIZE as u64)), Some(&1));
+            assert_eq!(map.len(), 2);
+
+            match map.entry_ref(&3) {
+                EntryRef::Occupied(mut e) => assert_eq!(e.insert(5), 2),
+                EntryRef::Vacant(_) => panic!("3 is in the map"),
+            }
+            assert_eq!(map.get(&3), Some(&5));
+        })
+    }
+
+    #[test]
+    fn raw_entry_hashed() {
+        on_big_stack(|| {
+            let mut map = IdMap::new();
+            match map.raw_entry_mut().from_key_hashed_nocheck(7, &7) {
+                RawEntryMut::Occupied(_) => panic!("the map is empty"),
+                RawEntryMut::Vacant(e) => *e.insert(Key(7), 0).1 = 1,
+            }
+            let (_, v) = map
+                .raw_entry_mut()
+                .from_key(&7)
+                .or_insert_with(|| (Key(7), 0));
+            assert_eq!(*v, 1);
+            match map.raw_entry_mut().from_key(&7) {
+                RawEntryMut::Occupied(e) => assert_eq!(e.remove_entry(), (Key(7), 1)),
+                RawEntryMut::Vacant(_) => panic!("7 is in the map"),
+            }
+            assert!(map.is_empty());
+        })
+    }
+
+    #[test]
+    fn remove_shifts_back_across_the_wrap() {
+        on_big_stack(|| {
+            let mut map = IdMap::new();
+            // a run starting on the last bucket and wrapping to the front, with
+            // a key at home in bucket 1 caught in the middle of it
+            let keys = [
+                LAST,
+                LAST + MAP_SIZE as u64,
+                1,
+                LAST + 2 * MAP_SIZE as u64,
+                MAP_SIZE as u64 + 1,
+            ];
+            for k in keys {
+                map.insert(Key(k), k);
+            }
+
+            assert_eq!(map.remove(&LAST), Some(LAST));
+            assert_eq!(map.remove(&LAST), None);
+            for k in &keys[1..] {
+                assert_eq!(map.get(k), Some(k));
+            }
+
+            assert_eq!(map.remove(&1), Some(1));
+            for k in [keys[1], keys[3], keys[4]] {
+                assert_eq!(map.get(&k), Some(&k));
+            }
+            assert_eq!(map.len(), 3);
+
+            // the run is tight again: nothing is left at home in bucket 1
+            assert!(map.content[2].0.is_none());
+            assert!(ctrl_consistent(&map));
+        })
+    }
+
+    #[test]
+    fn insert_replaces() {
+        on_big_stack(|| {
+            let mut map = IdMap::new();
+            assert_eq!(map.insert(Key(1), 1), None);
+            assert_eq!(map.insert(Key(1), 2), Some(1));
+            assert_eq!(map.get(&1), Some(&2));
+            assert_eq!(map.len(), 1);
+        })
+    }
+
+    #[test]
+    fn reinsert_when_full() {
+        on_big_stack(|| {
+            let mut map = IdMap::new();
+            let capacity = MAP_SIZE as u64 - 1;
+            for k in 0..capacity {
+                map.insert(Key(k), k);
+            }
+            // the capacity is reached, but replacing must still work
+            for k in 0..capacity {
+                assert_eq!(map.insert(Key(k), k + 1), Some(k));
+            }
+            assert_eq!(map.len(), MAP_SIZE - 1);
+        })
+    }
+
+    #[test]
+    #[should_panic(expected = "no space left")]
+    fn insert_past_capacity() {
+        on_big_stack(|| {
+            let mut map = IdMap::new();
+            for k in 0..MAP_SIZE as u64 {
+                map.insert(Key(k), k);
+            }
+        })
+    }
+
+    #[test]
+    fn probe_guard_and_rehash() {
+        on_big_stack(|| {
+            let mut map = StackMap::<Key, u64, Seed>::new_with_hasher(Seed(1));
+            // all at home in bucket 0, the last insertion walks one too far
+            let keys: Vec<_> = (0..PROBE_LIMIT as u64 + 2)
+                .map(|k| k * MAP_SIZE as u64)
+                .collect();
+            let (last, first) = keys.split_last().unwrap();
+            for &k in first {
+                map.insert(Key(k), k);
+            }
+            assert!(!map.is_clustered());
+            map.insert(Key(*last), *last);
+            assert!(map.is_clustered());
+
+            map.rehash(Seed(0x9e37_79b9_7f4a_7c15));
+            assert!(!map.is_clustered());
+            assert_eq!(map.len(), keys.len());
+            for k in &keys {
+                assert_eq!(map.get(k), Some(k));
+            }
+        })
+    }
+
+    /// Sums the bytes and lands them in a handful of buckets around the end of
+    /// the table, so that runs are long, full hashes collide and probes wrap.
+    #[derive(Default)]
+    struct Crowded(u64);
+
+    impl Hasher for Crowded {
+        fn finish(&self) -> u64 {
+            (MASK as u64 - 2) + self.0 % 8
+        }
+
+        fn write(&mut self, bytes: &[u8]) {
+            self.0 += bytes.iter().map(|&b| b as u64).sum::<u64>()
+        }
+    }
+
+    #[derive(Debug, Clone)]
+    enum Op {
+        Insert(Vec<u8>, u32),
+        Get(Vec<u8>),
+        Remove(Vec<u8>),
+        Entry(Vec<u8>, u32),
+    }
+
+    fn op() -> impl Strategy<Value = Op> {
+        // few short keys, so that most operations hit an existing key
+        let key = || vec(b'a'..=b'd', 0..4);
+        prop_oneof![
+            (key(), any::<u32>()).prop_map(|(k, v)| Op::Insert(k, v)),
+            key().prop_map(Op::Get),
+            key().prop_map(Op::Remove),
+            (key(), any::<u32>()).prop_map(|(k, v)| Op::Entry(k, v)),
+        ]
+    }
+
+    /// every control byte matches its bucket, the mirrored ones included
+    fn ctrl_consistent<K, V, H>(map: &StackMap<K, V, H>) -> bool {
+        let expected = |i: usize| match &map.content[i % MAP_SIZE].0 {
+            Some(b) => tag(b.hash_mem),
+            None => EMPTY,
+        };
+        (0..MAP_SIZE + GROUP).all(|i| map.ctrl[i] == expected(i))
+    }
+
+    fn same_as_std<H: BuildHasher + Default>(ops: Vec<Op>) -> Result<(), TestCaseError> {
+        let mut map: StackMap<Box<[u8]>, u32, H> = StackMap::new();
+        let mut std = HashMap::new();
+
+        for op in ops {
+            match op {
+                Op::Insert(k, v) => {
+                    prop_assert_eq!(map.insert(k.as_slice().into(), v), std.insert(k, v))
+                }
+                Op::Get(k) => prop_assert_eq!(map.get(k.as_slice()), std.get(&k)),
+                Op::Remove(k) => prop_assert_eq!(map.remove(k.as_slice()), std.remove(&k)),
+                Op::Entry(k, v) => {
+                    let ours = map.entry_ref(k.as_slice()).or_insert(v);
+                    let theirs = std.entry(k).or_insert(v);
+                    prop_assert_eq!(*ours, *theirs);
+                    *ours = ours.wrapping_add(1);
+                    *theirs = theirs.wrapping_add(1);
+                }
+            }
+            prop_assert_eq!(map.len(), std.len());
+        }
+
+        prop_assert_eq!(map.iter().count(), std.len());
+        prop_assert!(ctrl_consistent(&map));
+        for (k, v) in &std {
+            prop_assert_eq!(map.get(k.as_slice()), Some(v));
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn behaves_like_std_hashmap() {
+        on_big_stack(|| {
+            let ops = vec(op(), 0..500);
+            let mut runner = TestRunner::default();
+            runner.run(&ops, same_as_std::<FxBuildHasher>).unwrap();
+            runner
+                .run(&ops, same_as_std::<BuildHasherDefault<Crowded>>)
+                .unwrap();
+        })
     }
 }
@@ -0,0 +1,316 @@
+//! The aggregation and its parts, public for the binary and the benches
+#![feature(portable_simd)]
+#![feature(hasher_prefixfree_extras)]
+#![allow(nonstandard_style)]
+use std::{
+    borrow::{Borrow, BorrowMut},
+    collections::HashMap,
+    env,
+    fs::File,
+    hash::Hash,
+    io::{self, Write},
+    thread,
+};
+
+use memmap2::Mmap;
+use rustc_hash::FxBuildHasher;
+
+pub mod hasher;
+use hasher::{MHasher, MapHasher, Reseed, SeededHasherBuilder, StationHasherBuilder};
+
+pub mod cli;
+use cli::{Args, BackendKind, HasherKind, ScannerKind};
+
+mod backend;
+use backend::{AggMap, IntKeyMap, TableMap};
+
+pub mod parser;
+use parser::{Batch, Finder, Lines, Scanner, line_start, with_scanner};
+
+pub mod aggregator;
+use aggregator::Aggregator;
+
+pub mod stats;
+use stats::Stat;
+
+use crate::hashmap::StackMap;
+
+pub mod hashmap;
+
+pub mod merge;
+
+mod hash_quality;
+use hash_quality::HashStat;
+
+pub mod rng;
+
+pub mod stations;
+
+pub mod generate;
+
+pub mod bench;
+
+#[cfg(test)]
+mod reference;
+
+#[allow(nonstandard_style)]
+pub type fsize = i16;
+
+/// Keys pointing into the input. It is mapped for the whole run, so the hot
+/// loop never allocates.
+type BorrowedKey<'a> = &'a [u8];
+
+/// What the maps can be keyed by: anything built from a station name found in
+/// the input
+trait StationKey<'a>: From<&'a [u8]> + Borrow<[u8]> + Hash + Ord {}
+
+impl<'a, K> StationKey<'a> for K where K: From<&'a [u8]> + Borrow<[u8]> + Hash + Ord {}
+
+static FILE: &str = match option_env!("FILE") {
+    Some(x) => x,
+    None => "measurements.txt",
+};
+
+/// Aggregates [`FILE`] and prints the result
+pub fn challenge(args: &Args) {
+    challenge_with::<Stat>(args)
+}
+
+/// Aggregates [`FILE`] into an `A` per station, and prints what
+/// [`Aggregator::finish`] gives for each
+pub fn challenge_with<A: Aggregator>(args: &Args) {
+    let scanner = args.scanner.unwrap_or_else(ScannerKind::best);
+    if !scanner.is_available() {
+        eprintln!("error: the {scanner:?} scanner is not available on this cpu");
+        std::process::exit(2)
+    }
+
+    let f = File::open(FILE).unwrap();
+    let f = unsafe { Mmap::map(&f).unwrap() };
+    f.advise(memmap2::Advice::Sequential).unwrap();
+
+    // set the NUM_CPU env variable at compile time to change the number of cpu used. Defaults choosing the max at runtime
+    let n_cpus = env::vars()
+        .find(|x| x.0 == "NUM_CPU")
+        .map(|x| x.1.parse().unwrap())
+        .unwrap_or(num_cpus::get());
+    // let n_cpus = option_env!("NUM_CPU")
+    //     .map(|x| x.parse().unwrap())
+    //     .unwrap_or(num_cpus::get());
+    eprintln!("running on {n_cpus} threads");
+
+    aggregate_bytes::<A>(&f, args, scanner, n_cpus, &mut io::stdout().lock()).unwrap();
+}
+
+/// Aggregates `data` on `n_cpus` threads, with the hasher and the map of
+/// `args`, and prints the result to `out`. `scanner` must be available.
+pub fn aggregate_bytes<A: Aggregator>(
+    data: &[u8],
+    args: &Args,
+    scanner: ScannerKind,
+    n_cpus: usize,
+    out: &mut impl Write,
+) -> io::Result<()> {
+    match args.hasher {
+        None => run::<MHasher, A>(data, args, scanner, n_cpus, out),
+        Some(HasherKind::Fx) => run::<FxBuildHasher, A>(data, args, scanner, n_cpus, out),
+        Some(HasherKind::Station) => {
+            run::<StationHasherBuilder, A>(data, args, scanner, n_cpus, out)
+        }
+        Some(HasherKind::Seeded) => run::<SeededHasherBuilder, A>(data, args, scanner, n_cpus, out),
+    }
+}
+
+/// The whole run with the maps hashed by `H`
+fn run<H: MapHasher, A: Aggregator>(
+    f: &[u8],
+    args: &Args,
+    scanner: ScannerKind,
+    n_cpus: usize,
+    out: &mut impl Write,
+) -> io::Result<()> {
+    match args.backend {
+        BackendKind::Stack => aggregate::<StackMap<_, A, H>, _>(f, args, scanner, n_cpus, out),
+        BackendKind::Std => aggregate::<HashMap<_, A, H>, _>(f, args, scanner, n_cpus, out),
+        BackendKind::Hashbrown => aggregate::<TableMap<_, A, H>, _>(f, args, scanner, n_cpus, out),
+        BackendKind::Intmap => aggregate::<IntKeyMap<_, A, H>, _>(f, args, scanner, n_cpus, out),
+    }
+}
+
+/// Aggregates `f` into one `M` per thread, then merges them and prints the
+/// result to `out`
+fn aggregate<'a, M, A>(
+    f: &'a [u8],
+    args: &Args,
+    scanner: ScannerKind,
+    n_cpus: usize,
+    out: &mut impl Write,
+) -> io::Result<()>
+where
+    M: AggMap<BorrowedKey<'a>, A, Hasher: MapHasher> + HashStat + Send + Sync,
+    A: Aggregator,
+{
+    let chunk_size = f.len() / (n_cpus);
+    // only optimized builds keep the map on the stack, see `WorkerMap`
+    let map_size = if cfg!(debug_assertions) {
+        0
+    } else {
+        ::std::mem::size_of::<M>()
+    };
+    let stack_size = 2 * map_size + 2 * 1024 * 1024;
+    if args.stats {
+        eprintln!("stats stack_size={stack_size} threads={n_cpus}");
+    }
+
+    let results: Vec<WorkerMap<M>> = thread::scope(|sc| {
+        let handles: Vec<_> = (0..n_cpus)
+            .map(|i| {
+                // Create a builder with custom stack size
+                std::thread::Builder::new()
+                    .name(format!("worker-{}", i)) // Optional: helps with debugging
+                    .stack_size(stack_size) // Set to 32MB (adjust as needed)
+                    .spawn_scoped(sc, move || {
+                        let last = i + 1 == n_cpus;
+                        with_scanner!(scanner, |s| if args.batch {
+                            process(s, f, i, chunk_size, last, Batch::<_, _, LANES>::new)
+                        } else {
+                            process(s, f, i, chunk_size, last, Finder::new)
+                        })
+                        .expect("checked in `challenge_with`")
+                    })
+                    .expect("failed to spawn thread") // Builder returns a Result
+            })
+            .collect::<Vec<_>>();
+        handles.into_iter().map(|h| h.join().unwrap()).collect()
+    });
+
+    if args.stats {
+        for (i, map) in results.iter().enumerate() {
+            eprintln!("stats thread={i} {}", map.hash_stats());
+        }
+    }
+
+    let stats = M::merge_all(&results, n_cpus);
+    mprint(out, &stats)
+}
+
+/// Lines each thread parses in lockstep with `--batch`, see [`Batch`]
+const LANES: usize = 4;
+
+/// The map of a worker. Optimized builds keep it on the worker's stack.
+/// Unoptimized ones leave a copy in every frame it is moved through, so they
+/// box it instead of growing the stacks by some guess.
+#[cfg(not(debug_assertions))]
+type WorkerMap<M> = M;
+#[cfg(debug_assertions)]
+type WorkerMap<M> = Box<M>;
+
+/// Aggregates the `n`th part of `f`, `lines` builds the iterator over its
+/// lines (`Finder::new` or `Batch::new`)
+fn process<'a, K, M, A, S, I>(
+    scanner: S,
+    f: &'a [u8],
+    n: usize,
+    chunk_size: usize,
+    last: bool,
+    lines: fn(&'a [u8], usize, usize, M::Hasher, S) -> I,
+) -> WorkerMap<M>
+where
+    K: StationKey<'a>,
+    M: AggMap<K, A, Hasher: MapHasher>,
+    A: Aggregator,
+    S: Scanner,
+    I: Lines<'a, M::Hasher>,
+{
+    #[cfg(not(debug_assertions))]
+    let mut stats = M::new_with_hasher(Default::default());
+    #[cfg(debug_assertions)]
+    let mut stats = M::new_boxed_with_hasher(Default::default());
+    // the lines starting in the part, up to where the next one starts
+    let start = line_start(f, n * chunk_size);
+    let next = if last {
+        f.len()
+    } else {
+        line_start(f, (n + 1) * chunk_size)
+    };
+    if start == next {
+        return stats;
+    }
+    let mut iter = lines(f, start, next - 1, stats.hasher().clone(), scanner);
+
+    let (mut guarded, mut reseeds) = (true, 0);
+    // the whole loop gets the cpu features, so the scan inlines into it
+    scanner.run(|| {
+        while let Some((station, hashed, temperature)) = iter.next() {
+            // `hashed` comes from the parser, so the name isn't hashed again
+            stats.get_or_default(hashed, station).update(temperature);
+
+            if guarded && stats.is_clustered() {
+                guarded = unclutter(
+                    BorrowMut::<M>::borrow_mut(&mut stats),
+                    &mut iter,
+                    &mut reseeds,
+                    n,
+                );
+            }
+        }
+    });
+
+    stats
+}
+
+/// The probe guard went off: start over with a fresh seed if the hasher has
+/// one. Otherwise, or after too many tries, keep going with the clustered
+/// map, slower but still correct. Returns whether to keep guarding.
+#[cold]
+#[inline(never)]
+fn unclutter<'a, K, M, A>(
+    stats: &mut M,
+    iter: &mut impl Lines<'a, M::Hasher>,
+    reseeds: &mut usize,
+    thread: usize,
+) -> bool
+where
+    K: StationKey<'a>,
+    M: AggMap<K, A, Hasher: MapHasher>,
+    A: Aggregator,
+{
+    static MAX_RESEEDS: usize = 4;
+    match stats.hasher().reseed() {
+        Some(hasher) if *reseeds < MAX_RESEEDS => {
+            *reseeds += 1;
+            stats.rehash(hasher.clone());
+            iter.set_hasher(hasher);
+            true
+        }
+        _ => {
+            eprintln!(
+                "warning: thread {thread}: the keys cluster in the map, lookups will be slow \
+                 (try --hasher seeded)"
+            );
+            false
+        }
+    }
+}
+
+/// outputs the results, `stats` is sorted by station. The names are written
+/// as they are in the input.
+fn mprint<K: Borrow<[u8]>, A: Aggregator>(
+    out: &mut impl Write,
+    stats: &[(&K, A)],
+) -> io::Result<()> {
+    write!(out, "{{")?;
+    for (i, (station, stat)) in stats.iter().enumerate() {
+        if i != 0 {
+            write!(out, ", ")?;
+        }
+        out.write_all(mas_slice(*station))?;
+        write!(out, "={}", stat.finish())?;
+    }
+    writeln!(out, "}}")
+}
+
+/// To be able to more easly swap the array type
+fn mas_slice<K: Borrow<[u8]>>(x: &K) -> &[u8] {
+    x.borrow()
+}
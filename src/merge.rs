@@ -0,0 +1,163 @@
+//! Combines the per-thread maps into a single result
+
+use std::{
+    borrow::Borrow,
+    hash::{BuildHasher, Hash},
+    thread,
+};
+
+use crate::hashmap::StackMap;
+
+/// Values that can be combined when the same key shows up in several maps
+pub trait Merge {
+    fn merge(&mut self, other: &Self);
+}
+
+/// Merges `maps` on `n_threads` threads, returning the entries sorted by key.
+///
+/// When all the maps hash the same way, a key has the same home bucket in
+/// every map. The home buckets are then split in `n_threads` ranges, and each
+/// thread merges the entries at home in its range, without any
+/// synchronisation. Otherwise everything is merged on the current thread.
+/// The maps can be borrowed, the workers of unoptimized builds return theirs
+/// boxed.
+pub fn par_merge<'m, K, V, H, B>(maps: &'m [B], n_threads: usize) -> Vec<(&'m K, V)>
+where
+    B: Borrow<StackMap<K, V, H>> + Sync,
+    K: Hash + Ord + Sync,
+    V: Merge + Clone + Send + Sync + 'm,
+    H: BuildHasher + Sync + 'm,
+{
+    let maps = || maps.iter().map(B::borrow);
+    let same_layout = maps().zip(maps().skip(1)).all(|(a, b)| a.same_layout(b));
+    let mut all = if same_layout && n_threads > 1 {
+        let buckets = StackMap::<K, V, H>::buckets();
+        let step = buckets.div_ceil(n_threads);
+        thread::scope(|sc| {
+            let handles: Vec<_> = (0..buckets)
+                .step_by(step)
+                .map(|start| {
+                    let homes = start..(start + step).min(buckets);
+                    sc.spawn(move || merge_sorted(maps().flat_map(|m| m.iter_homes(homes.clone()))))
+                })
+                .collect();
+            handles
+                .into_iter()
+                .flat_map(|h| h.join().unwrap())
+                .collect::<Vec<_>>()
+        })
+    } else {
+        // the hashes of a key may differ between maps, only compare keys
+        merge_unordered(maps().flat_map(|m| m.iter()))
+    };
+
+    all.sort_unstable_by_key(|(k, _)| *k);
+    all
+}
+
+/// Merges entries that may come from maps hashing differently, sorted by key
+pub fn merge_unordered<'a, K, V>(entries: impl Iterator<Item = (&'a K, &'a V)>) -> Vec<(&'a K, V)>
+where
+    K: Ord + 'a,
+    V: Merge + Clone + 'a,
+{
+    // with the same dummy hash, `merge_sorted` sorts by key
+    merge_sorted(entries.map(|(k, v)| (0, k, v)))
+}
+
+/// Sorts the entries by hash then key, and merges the runs of equal keys.
+/// The hash is only there to make comparisons cheap, so the entries must all
+/// come from maps that hash the same way, or all have the same dummy hash.
+fn merge_sorted<'a, K, V>(entries: impl Iterator<Item = (u64, &'a K, &'a V)>) -> Vec<(&'a K, V)>
+where
+    K: Ord,
+    V: Merge + Clone + 'a,
+{
+    let mut entries: Vec<_> = entries.collect();
+    entries.sort_unstable_by(|(ha, ka, _), (hb, kb, _)| ha.cmp(hb).then_with(|| ka.cmp(kb)));
+
+    let mut merged: Vec<(&K, V)> = Vec::with_capacity(entries.len());
+    let mut last_hash = None;
+    for (hashed, key, value) in entries {
+        match merged.last_mut() {
+            Some((k, v)) if last_hash == Some(hashed) && *k == key => v.merge(value),
+            _ => merged.push((key, value.clone())),
+        }
+        last_hash = Some(hashed);
+    }
+    merged
+}
+
+#[cfg(test)]
+mod test {
+    use std::{collections::BTreeMap, hash::BuildHasher};
+
+    use ahash::RandomState;
+    use rustc_hash::FxBuildHasher;
+
+    use super::{Merge, par_merge};
+    use crate::hashmap::{StackMap, test::on_big_stack};
+
+    #[derive(Clone, Copy, Default, Debug, PartialEq)]
+    struct Sum(u64);
+
+    impl Merge for Sum {
+        fn merge(&mut self, other: &Self) {
+            self.0 += other.0
+        }
+    }
+
+    /// `n_maps` maps, each with an overlapping slice of the keys
+    fn maps<H: BuildHasher>(n_maps: u64, hasher: impl Fn() -> H) -> Vec<StackMap<u64, Sum, H>> {
+        (0..n_maps)
+            .map(|m| {
+                let mut map = StackMap::new_with_hasher(hasher());
+                for k in (m * 500)..(m * 500 + 3000) {
+                    map.insert(k, Sum(k + m));
+                }
+                map
+            })
+            .collect()
+    }
+
+    fn expected(n_maps: u64) -> BTreeMap<u64, Sum> {
+        let mut ret = BTreeMap::new();
+        for m in 0..n_maps {
+            for k in (m * 500)..(m * 500 + 3000) {
+                ret.entry(k).or_insert(Sum(0)).merge(&Sum(k + m));
+            }
+        }
+        ret
+    }
+
+    #[test]
+    fn par_merge_same_layout() {
+        on_big_stack(|| {
+            let maps = maps(4, || FxBuildHasher);
+            let expected: Vec<_> = expected(4).into_iter().collect();
+            for n_threads in [1, 2, 3, 7] {
+                let merged: Vec<_> = par_merge(&maps, n_threads)
+                    .into_iter()
+                    .map(|(k, v)| (*k, v))
+                    .collect();
+                assert_eq!(merged, expected);
+            }
+        })
+    }
+
+    #[test]
+    fn merge_different_layouts() {
+        on_big_stack(|| {
+            // one seed per map, so the stored hashes can't be reused
+            let expected: Vec<_> = expected(3).into_iter().collect();
+            let seeded = maps(3, RandomState::new);
+            assert!(!seeded[0].same_layout(&seeded[1]));
+
+            let merged: Vec<_> = par_merge(&seeded, 4)
+                .into_iter()
+                .map(|(k, v)| (*k, v))
+                .collect();
+            assert_eq!(merged, expected);
+        })
+    }
+}
@@ -0,0 +1,110 @@
+//! A slow and obviously correct version of the whole run, to check the fast
+//! one against
+
+use std::collections::BTreeMap;
+
+/// What a run prints for `input`, with `str::split` and `f64`
+pub fn aggregate(input: &str) -> String {
+    // min, max, sum and count
+    let mut stations: BTreeMap<&str, (f64, f64, f64, u64)> = BTreeMap::new();
+    for line in input.split_terminator('\n') {
+        let (station, temperature) = line.split_once(';').unwrap();
+        let temperature: f64 = temperature.parse().unwrap();
+        let (min, max, sum, count) =
+            stations
+                .entry(station)
+                .or_insert((f64::INFINITY, f64::NEG_INFINITY, 0., 0));
+        *min = min.min(temperature);
+        *max = max.max(temperature);
+        // the temperatures have one decimal, summed as tenths they add up
+        // exactly
+        *sum += (temperature * 10.).round();
+        *count += 1;
+    }
+
+    let stations: Vec<_> = stations
+        .into_iter()
+        .map(|(station, (min, max, sum, count))| {
+            // rounded half up, like Java's `Math.round`. The quotient of the
+            // tenths is only exactly half way on a tie
+            let mean = (sum / count as f64 + 0.5).floor() / 10.;
+            // adding 0 turns -0.0 into 0.0
+            format!("{station}={:.1}/{:.1}/{:.1}", min + 0., mean + 0., max + 0.)
+        })
+        .collect();
+    format!("{{{}}}\n", stations.join(", "))
+}
+
+#[cfg(test)]
+mod test {
+    use rustc_hash::FxBuildHasher;
+
+    use crate::{
+        BorrowedKey, aggregate,
+        cli::{Args, ScannerKind},
+        hashmap::StackMap,
+        rng::{SplitMix64, random_names},
+        stats::Stat,
+    };
+
+    /// `n_lines` lines of `n_stations` names, the last one without its `\n`
+    /// unless `newline`
+    fn generate(rng: &mut SplitMix64, n_stations: usize, n_lines: usize, newline: bool) -> String {
+        let names = random_names(rng, n_stations);
+        let mut data: String = (0..n_lines)
+            .map(|_| {
+                let name = &names[rng.below(n_stations as u64) as usize];
+                let t = rng.below(1999) as i16 - 999;
+                let sign = if t < 0 { "-" } else { "" };
+                let name = str::from_utf8(name).unwrap();
+                format!("{name};{sign}{}.{}\n", t.abs() / 10, t.abs() % 10)
+            })
+            .collect();
+        if !newline {
+            data.pop();
+        }
+        data
+    }
+
+    /// What the fast version prints for `data`
+    fn run(data: &[u8], scanner: ScannerKind, n_threads: usize, batch: bool) -> String {
+        let args = Args {
+            batch,
+            ..Default::default()
+        };
+        let mut out = vec![];
+        aggregate::<StackMap<BorrowedKey, Stat, FxBuildHasher>, _>(
+            data, &args, scanner, n_threads, &mut out,
+        )
+        .unwrap();
+        String::from_utf8(out).unwrap()
+    }
+
+    /// every line must be counted once, whichever thread's part it starts at
+    #[test]
+    fn same_as_reference() {
+        let mut rng = SplitMix64::new(3);
+        for (n_stations, n_lines) in [(1, 1), (3, 10), (50, 200), (413, 3000), (2000, 8000)] {
+            for newline in [true, false] {
+                let data = generate(&mut rng, n_stations, n_lines, newline);
+                let expected = super::aggregate(&data);
+                // exactly sized, so that reading past the end is caught
+                let data: Box<[u8]> = data.as_bytes().into();
+                for n_threads in [1, 2, 3, 8] {
+                    for (scanner, batch) in [
+                        (ScannerKind::best(), false),
+                        (ScannerKind::best(), true),
+                        (ScannerKind::Swar, false),
+                    ] {
+                        assert_eq!(
+                            run(&data, scanner, n_threads, batch),
+                            expected,
+                            "{n_lines} lines of {n_stations} stations on {n_threads} \
+                             threads, {scanner:?} batch={batch}"
+                        );
+                    }
+                }
+            }
+        }
+    }
+}
@@ -0,0 +1,64 @@
+//! The seeded generator behind the generated files and the test keys, so
+//! that both are reproducible.
+
+use std::collections::HashSet;
+
+/// Small seeded generator (SplitMix64), good enough to make test keys
+pub struct SplitMix64(u64);
+
+impl SplitMix64 {
+    pub fn new(seed: u64) -> Self {
+        Self(seed)
+    }
+
+    pub fn next_u64(&mut self) -> u64 {
+        self.0 = self.0.wrapping_add(0x9e37_79b9_7f4a_7c15);
+        let mut z = self.0;
+        z = (z ^ (z >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+        z ^ (z >> 31)
+    }
+
+    /// uniform in `0..n`
+    pub fn below(&mut self, n: u64) -> u64 {
+        ((self.next_u64() as u128 * n as u128) >> 64) as u64
+    }
+}
+
+/// `n` distinct names valid for the challenge (1 to 100 bytes, no `;`),
+/// mostly short like the real ones
+pub fn random_names(rng: &mut SplitMix64, n: usize) -> Vec<Vec<u8>> {
+    static ALPHABET: &[u8] = b"abcdefghijklmnopqrstuvwxyzABCDEFGHIJKLMNOPQRSTUVWXYZ -'.,()";
+    let mut seen = HashSet::with_capacity(n);
+    while seen.len() < n {
+        let len = match rng.below(20) {
+            0 => 32 + rng.below(69),
+            1..=4 => 16 + rng.below(16),
+            _ => 1 + rng.below(15),
+        };
+        let name = (0..len)
+            .map(|_| ALPHABET[rng.below(ALPHABET.len() as u64) as usize])
+            .collect::<Vec<_>>();
+        seen.insert(name);
+    }
+    let mut names: Vec<_> = seen.into_iter().collect();
+    // `HashSet` iteration order is random, keep the results reproducible
+    names.sort_unstable();
+    names
+}
+
+#[cfg(test)]
+mod test {
+    use super::{SplitMix64, random_names};
+
+    #[test]
+    fn random_names_are_valid() {
+        let names = random_names(&mut SplitMix64::new(0x1b2c), 1000);
+        assert_eq!(names.len(), 1000);
+        assert!(
+            names
+                .iter()
+                .all(|n| (1..=100).contains(&n.len()) && !n.contains(&b';'))
+        );
+    }
+}
@@ -1,13 +1,14 @@
 use std::fmt::Display;
 
-use crate::fsize;
+use crate::{aggregator::Aggregator, fsize, merge::Merge};
 
-#[derive(Clone, Copy)]
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
 pub struct Stat {
     pub min: fsize,
     pub max: fsize,
     pub sum: i64,
-    pub count: u32,
+    /// `u64`, so that aggregating several files can't wrap it
+    pub count: u64,
 }
 
 impl Default for Stat {
@@ -22,6 +23,8 @@ impl Default for Stat {
 }
 
 impl Display for Stat {
+    /// `min/mean/max` with one decimal, the exact mean rounded half up like
+    /// Java's `Math.round` and never `-0.0`, as the rules ask
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         let Stat {
             min,
@@ -29,21 +32,177 @@ impl Display for Stat {
             sum,
             count,
         } = *self;
-        let mean = (sum as f64) / (10. * count as f64);
-        let min = (min as f64) / 10.;
-        let max = (max as f64) / 10.;
-        // safe
-        write!(f, "{min:.1}/{mean:.1}/{max:.1}")
+        // floor(sum / count + 1/2), in integers so that ties are exact
+        let (sum, count) = (i128::from(sum), i128::from(count));
+        let mean = (2 * sum + count).div_euclid(2 * count) as i64;
+        write!(
+            f,
+            "{}/{}/{}",
+            Tenths(min.into()),
+            Tenths(mean),
+            Tenths(max.into())
+        )
+    }
+}
+
+/// A number of tenths, written with its one decimal
+struct Tenths(i64);
+
+impl Display for Tenths {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let sign = if self.0 < 0 { "-" } else { "" };
+        let abs = self.0.unsigned_abs();
+        write!(f, "{sign}{}.{}", abs / 10, abs % 10)
+    }
+}
+
+impl Aggregator for Stat {
+    /// Printed as it is, see the `Display` impl
+    type Output = Self;
+
+    #[inline(always)]
+    fn update(&mut self, temperature: fsize) {
+        self.min = self.min.min(temperature);
+        self.max = self.max.max(temperature);
+        self.sum += i64::from(temperature);
+        self.count += 1;
+    }
+
+    fn finish(&self) -> Self {
+        *self
     }
 }
 
 impl Stat {
+    /// Combines the stats of a station, panics if the sum or the count
+    /// overflow
     pub fn reduce(iter: impl IntoIterator<Item = Self>) -> Option<Self> {
-        iter.into_iter().reduce(|a, b| Self {
-            min: a.min.min(b.min),
-            max: a.max.max(b.max),
-            sum: a.sum + b.sum,
-            count: a.count + b.count,
+        iter.into_iter().reduce(|mut a, b| {
+            a.merge(&b);
+            a
         })
     }
 }
+
+impl Merge for Stat {
+    /// Once per station and thread, cheap enough to always be checked
+    fn merge(&mut self, other: &Self) {
+        self.min = self.min.min(other.min);
+        self.max = self.max.max(other.max);
+        self.sum = self
+            .sum
+            .checked_add(other.sum)
+            .expect("the sum of the temperatures overflows");
+        self.count = self
+            .count
+            .checked_add(other.count)
+            .expect("the count of the rows overflows");
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use rustc_hash::FxBuildHasher;
+
+    use super::Stat;
+    use crate::{
+        BorrowedKey, aggregate,
+        aggregator::Aggregator,
+        cli::{Args, ScannerKind},
+        hashmap::StackMap,
+    };
+
+    fn stat(temperatures: &str) -> Stat {
+        let mut stat = Stat::default();
+        for t in temperatures.split(' ') {
+            stat.update((t.parse::<f64>().unwrap() * 10.).round() as _);
+        }
+        stat
+    }
+
+    /// the rules: the mean rounded half up, never `-0.0`. The expected
+    /// strings are worked out by hand from the exact means.
+    #[test]
+    fn rounds_half_up_without_negative_zero() {
+        for (temperatures, expected) in [
+            ("-0.0", "0.0/0.0/0.0"),
+            ("-0.1", "-0.1/-0.1/-0.1"),
+            ("-0.1 0.0", "-0.1/0.0/0.0"),
+            ("0.1 0.0", "0.0/0.1/0.1"),
+            ("-0.1 0.1 -0.1 0.1", "-0.1/0.0/0.1"),
+            ("0.2 0.3", "0.2/0.3/0.3"),
+            ("-0.2 -0.3", "-0.3/-0.2/-0.2"),
+            ("-0.1 -0.1 0.1", "-0.1/0.0/0.1"),
+            ("-0.2 0.1", "-0.2/0.0/0.1"),
+            ("-0.5 0.4", "-0.5/0.0/0.4"),
+            ("1.2 1.3", "1.2/1.3/1.3"),
+            ("-1.2 -1.3", "-1.3/-1.2/-1.2"),
+            ("0.1 0.1 0.2", "0.1/0.1/0.2"),
+            ("-0.1 -0.1 -0.2", "-0.2/-0.1/-0.1"),
+            ("-0.1 -0.1 -0.1 0.2", "-0.1/0.0/0.2"),
+            ("12.3 -12.4", "-12.4/0.0/12.3"),
+            ("99.9 99.8", "99.8/99.9/99.9"),
+            ("-99.9 -99.8", "-99.9/-99.8/-99.8"),
+            ("-99.9", "-99.9/-99.9/-99.9"),
+            ("99.9", "99.9/99.9/99.9"),
+        ] {
+            assert_eq!(stat(temperatures).to_string(), expected, "{temperatures}");
+        }
+        // -99.85 exactly, rounded up to -99.8. The Java baseline prints -99.9
+        // here: it divides in doubles and lands just below the tie. This
+        // integer rounding differs from it on such ties.
+        assert_eq!(
+            stat("-99.9 -99.9 -99.9 -99.9 -99.9 -99.6").to_string(),
+            "-99.9/-99.8/-99.6"
+        );
+    }
+
+    /// `fixtures/rounding` has boundary cases for the mean, and what the Java
+    /// baseline prints for them (`run-solution` in that directory). They only
+    /// differ on case 21, the -99.85 tie above that the baseline lands below.
+    #[test]
+    fn matches_the_baseline_except_below_ties() {
+        let data = include_bytes!("../fixtures/rounding/measurements.txt");
+        let baseline = include_str!("../fixtures/rounding/baseline.txt");
+        let mut out = vec![];
+        aggregate::<StackMap<BorrowedKey, Stat, FxBuildHasher>, _>(
+            data,
+            &Args::default(),
+            ScannerKind::Swar,
+            1,
+            &mut out,
+        )
+        .unwrap();
+
+        let (theirs, ours) = ("case 21=-99.9/-99.9/-99.6", "case 21=-99.9/-99.8/-99.6");
+        assert!(baseline.contains(theirs));
+        assert_eq!(
+            String::from_utf8(out).unwrap(),
+            baseline.replace(theirs, ours)
+        );
+    }
+
+    #[test]
+    fn counts_past_u32() {
+        // the stats of 3 billion rows of 99.9 and one of -99.9, per thread
+        let thread = Stat {
+            min: -999,
+            max: 999,
+            sum: 999 * 3_000_000_000 - 999,
+            count: 3_000_000_001,
+        };
+        let all = Stat::reduce([thread; 4]).unwrap();
+        assert_eq!(all.count, 12_000_000_004);
+        assert_eq!(all.to_string(), "-99.9/99.9/99.9");
+    }
+
+    #[test]
+    #[should_panic(expected = "overflows")]
+    fn overflows_are_caught() {
+        let huge = Stat {
+            sum: i64::MAX / 2 + 1,
+            ..stat("1.0")
+        };
+        Stat::reduce([huge, huge]);
+    }
+}